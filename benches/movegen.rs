@@ -0,0 +1,26 @@
+// Perft speed with each board representation, to show what the bitboards are worth. Run
+// with `cargo bench --bench movegen`.
+use chess::engine::{Backend, ChessEngine};
+use chess::fen;
+use std::time::Instant;
+
+const POSITIONS: [(&str, &str, u32); 3] = [
+    ("start", fen::STARTING_FEN, 4),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5),
+];
+
+fn main() {
+    println!("{:<10} {:<9} {:>6} {:>10} {:>10} {:>8}", "position", "backend", "depth", "nodes", "nps", "ms");
+    for (name, fen, depth) in POSITIONS.iter() {
+        let board = fen::parse_fen(fen).unwrap();
+        for backend in [Backend::Mailbox, Backend::Bitboard].iter() {
+            let start = Instant::now();
+            let nodes = ChessEngine::perft_with(&board, *depth, *backend).unwrap();
+            let elapsed = start.elapsed();
+            let nps = (nodes as u128 * 1_000_000_000 / elapsed.as_nanos().max(1)) as u64;
+            println!("{:<10} {:<9} {:>6} {:>10} {:>10} {:>8}", name, format!("{:?}", backend).to_lowercase(),
+                     depth, nodes, nps, elapsed.as_millis());
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// Node counts for a fixed-depth search with the search features added one at a time, to
+// show what each is worth. Run with `cargo bench --bench search`.
+use chess::engine::{ChessEngine, SearchFeatures, SearchLimits};
+use chess::fen;
+
+const DEPTH: u32 = 4;
+
+const POSITIONS: [(&str, &str); 3] = [
+    ("start", fen::STARTING_FEN),
+    ("italian", "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+];
+
+fn main() {
+    let configurations = [
+        ("alpha-beta", SearchFeatures::none()),
+        ("+ mvv-lva/see", SearchFeatures { capture_ordering: true, ..SearchFeatures::none() }),
+        ("+ killers/history", SearchFeatures { quiescence: false, ..SearchFeatures::default() }),
+        ("+ quiescence", SearchFeatures::default()),
+    ];
+    println!("{:<10} {:<18} {:>10} {:>10} {:>8}", "position", "search", "nodes", "nps", "ms");
+    for (name, fen) in POSITIONS.iter() {
+        let board = fen::parse_fen(fen).unwrap();
+        for (label, features) in configurations.iter() {
+            let limits = SearchLimits { features: *features, ..SearchLimits::depth(DEPTH) };
+            let stats = ChessEngine::best_moves(&board, &limits).unwrap().stats;
+            println!("{:<10} {:<18} {:>10} {:>10} {:>8}", name, label, stats.nodes, stats.nps, stats.time_ms);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use chess::uci::UciEngine;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::thread;
+
+// speaks UCI over stdin and stdout, so that the engine can be used from chess GUIs
+fn main() {
+    let (out, replies) = mpsc::channel::<String>();
+    // replies come from the search thread as well as from the commands themselves
+    let printer = thread::spawn(move || {
+        let stdout = io::stdout();
+        for line in replies {
+            let mut stdout = stdout.lock();
+            if writeln!(stdout, "{}", line).and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+        }
+    });
+
+    // the engine is dropped once the input ends, which closes the channel after the last reply
+    UciEngine::new(out).run(io::stdin().lock());
+    printer.join().unwrap();
+}
@@ -0,0 +1,247 @@
+use super::board::{Color, Piece, PieceKind, Position};
+use serde::{Serialize, Deserialize};
+
+/// A set of squares, one bit each, with a1 as bit 0, b1 as bit 1 and h8 as bit 63.
+pub type Bitboard = u64;
+
+pub fn square(pos: &Position) -> usize {
+    (pos.row * 8 + pos.col) as usize
+}
+
+pub fn position(square: usize) -> Position {
+    Position::new(square as i32 / 8, square as i32 % 8)
+}
+
+pub fn bit(pos: &Position) -> Bitboard {
+    1 << square(pos)
+}
+
+/// The squares in `bitboard`, lowest first.
+pub fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let square = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(square)
+    })
+}
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i32, i32); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+// the rays sliders move along; the first four run towards higher squares
+const DIRECTIONS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (1, -1), (-1, 0), (-1, -1), (0, -1), (-1, 1)];
+const ROOK_DIRECTIONS: [usize; 4] = [0, 2, 4, 6];
+const BISHOP_DIRECTIONS: [usize; 4] = [1, 3, 5, 7];
+
+// the squares one step away from each square in each of `steps`, or every square along them
+// when `slide` is set
+const fn step_table(steps: &[(i32, i32)], slide: bool) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut i = 0;
+        while i < steps.len() {
+            let (mut row, mut col) = (square as i32 / 8, square as i32 % 8);
+            loop {
+                row += steps[i].0;
+                col += steps[i].1;
+                if row < 0 || row >= 8 || col < 0 || col >= 8 {
+                    break;
+                }
+                table[square] |= 1 << (row * 8 + col);
+                if !slide {
+                    break;
+                }
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+const fn ray_tables() -> [[Bitboard; 64]; 8] {
+    let mut rays = [[0; 64]; 8];
+    let mut direction = 0;
+    while direction < 8 {
+        rays[direction] = step_table(&[DIRECTIONS[direction]], true);
+        direction += 1;
+    }
+    rays
+}
+
+const KNIGHT_ATTACKS: [Bitboard; 64] = step_table(&KNIGHT_STEPS, false);
+const KING_ATTACKS: [Bitboard; 64] = step_table(&KING_STEPS, false);
+const PAWN_ATTACKS: [[Bitboard; 64]; 2] = [
+    step_table(&[(1, -1), (1, 1)], false),
+    step_table(&[(-1, -1), (-1, 1)], false),
+];
+const RAYS: [[Bitboard; 64]; 8] = ray_tables();
+
+pub fn knight_attacks(square: usize) -> Bitboard {
+    KNIGHT_ATTACKS[square]
+}
+
+pub fn king_attacks(square: usize) -> Bitboard {
+    KING_ATTACKS[square]
+}
+
+/// The squares a pawn of `color` on `square` attacks.
+pub fn pawn_attacks(square: usize, color: Color) -> Bitboard {
+    PAWN_ATTACKS[color_index(color)][square]
+}
+
+pub fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    ROOK_DIRECTIONS.iter().fold(0, |attacks, direction| attacks | ray_attacks(square, occupied, *direction))
+}
+
+pub fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    BISHOP_DIRECTIONS.iter().fold(0, |attacks, direction| attacks | ray_attacks(square, occupied, *direction))
+}
+
+pub fn queen_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+// the ray up to and including the first piece in the way, which is the nearest set bit: the
+// lowest on rays towards higher squares and the highest on the others
+fn ray_attacks(square: usize, occupied: Bitboard, direction: usize) -> Bitboard {
+    let ray = RAYS[direction][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let blocker = if direction < 4 { blockers.trailing_zeros() } else { 63 - blockers.leading_zeros() };
+    ray ^ RAYS[direction][blocker as usize]
+}
+
+/// The squares `kind` attacks from `square`, with `occupied` blocking the sliders. Pawns
+/// attack differently for each color, so they are left to `pawn_attacks`.
+pub fn attacks(kind: PieceKind, square: usize, occupied: Bitboard) -> Bitboard {
+    match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => knight_attacks(square),
+        PieceKind::Bishop => bishop_attacks(square, occupied),
+        PieceKind::Rook => rook_attacks(square, occupied),
+        PieceKind::Queen => queen_attacks(square, occupied),
+        PieceKind::King => king_attacks(square),
+    }
+}
+
+fn color_index(color: Color) -> usize {
+    if color == Color::White { 0 } else { 1 }
+}
+
+fn kind_index(kind: PieceKind) -> usize {
+    match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5,
+    }
+}
+
+/// Where the pieces stand, as one bitboard per kind and color, kept alongside the board's
+/// map of pieces for fast attack detection and move generation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bitboards {
+    pieces: [[Bitboard; 6]; 2],
+    colors: [Bitboard; 2],
+}
+
+impl Bitboards {
+
+    /// Adds `piece` on `square` if it is not there, and removes it if it is.
+    pub fn toggle(&mut self, piece: &Piece, square: usize) {
+        let color = color_index(piece.color);
+        self.pieces[color][kind_index(piece.kind)] ^= 1 << square;
+        self.colors[color] ^= 1 << square;
+    }
+
+    pub fn pieces(&self, color: Color, kind: PieceKind) -> Bitboard {
+        self.pieces[color_index(color)][kind_index(kind)]
+    }
+
+    pub fn color(&self, color: Color) -> Bitboard {
+        self.colors[color_index(color)]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn king(&self, color: Color) -> Option<usize> {
+        squares(self.pieces(color, PieceKind::King)).next()
+    }
+
+    /// The pieces of `color` attacking `square`, with `occupied` standing in for the board's
+    /// own occupancy so that sliders can be made to see through pieces.
+    pub fn attackers(&self, square: usize, color: Color, occupied: Bitboard) -> Bitboard {
+        let diagonal = self.pieces(color, PieceKind::Bishop) | self.pieces(color, PieceKind::Queen);
+        let straight = self.pieces(color, PieceKind::Rook) | self.pieces(color, PieceKind::Queen);
+        // a pawn of `color` attacks the squares a pawn of the other color would attack from here
+        (pawn_attacks(square, color.opposite()) & self.pieces(color, PieceKind::Pawn))
+            | (knight_attacks(square) & self.pieces(color, PieceKind::Knight))
+            | (king_attacks(square) & self.pieces(color, PieceKind::King))
+            | (bishop_attacks(square, occupied) & diagonal)
+            | (rook_attacks(square, occupied) & straight)
+    }
+
+    pub fn is_attacked(&self, square: usize, color: Color) -> bool {
+        self.attackers(square, color, self.occupied()) != 0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(names: &[&str]) -> Bitboard {
+        names.iter().map(|name| bit(&Position::from_algebraic(name).unwrap())).fold(0, |all, b| all | b)
+    }
+
+    fn sq(name: &str) -> usize {
+        square(&Position::from_algebraic(name).unwrap())
+    }
+
+    #[test]
+    fn test_leaper_attacks() {
+        assert_eq!(knight_attacks(sq("a1")), bits(&["b3", "c2"]));
+        assert_eq!(knight_attacks(sq("d4")).count_ones(), 8);
+        assert_eq!(king_attacks(sq("h8")), bits(&["g8", "g7", "h7"]));
+        assert_eq!(pawn_attacks(sq("a2"), Color::White), bits(&["b3"]));
+        assert_eq!(pawn_attacks(sq("e7"), Color::Black), bits(&["d6", "f6"]));
+        assert_eq!(pawn_attacks(sq("e8"), Color::White), 0);
+    }
+
+    #[test]
+    fn test_slider_attacks() {
+        assert_eq!(rook_attacks(sq("a1"), 0).count_ones(), 14);
+        assert_eq!(bishop_attacks(sq("d4"), 0).count_ones(), 13);
+        let occupied = bits(&["d6", "b4", "f2", "b2"]);
+        assert_eq!(rook_attacks(sq("d4"), occupied),
+                   bits(&["d5", "d6", "d3", "d2", "d1", "c4", "b4", "e4", "f4", "g4", "h4"]));
+        assert_eq!(bishop_attacks(sq("d4"), occupied),
+                   bits(&["c5", "b6", "a7", "e5", "f6", "g7", "h8", "c3", "b2", "e3", "f2"]));
+    }
+
+    #[test]
+    fn test_attackers() {
+        let mut boards = Bitboards::default();
+        boards.toggle(&Piece::new(PieceKind::Rook, Color::Black), sq("e8"));
+        boards.toggle(&Piece::new(PieceKind::Pawn, Color::Black), sq("d2"));
+        boards.toggle(&Piece::new(PieceKind::Knight, Color::White), sq("e4"));
+        assert_eq!(boards.attackers(sq("e1"), Color::Black, boards.occupied()), bits(&["d2"]));
+        assert_eq!(boards.attackers(sq("e1"), Color::Black, boards.occupied() & !bits(&["e4"])), bits(&["d2", "e8"]));
+        assert!(boards.is_attacked(sq("d2"), Color::White));
+        assert!(!boards.is_attacked(sq("e2"), Color::White));
+        assert_eq!(boards.king(Color::White), None);
+        boards.toggle(&Piece::new(PieceKind::Knight, Color::White), sq("e4"));
+        assert_eq!(boards.color(Color::White), 0);
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use serde::{Serialize, Deserialize};
+use super::bitboard::{self, Bitboards};
+use super::zobrist;
 
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum PieceKind {
@@ -12,12 +14,47 @@ pub enum PieceKind {
     Pawn,
 }
 
+impl PieceKind {
+    /// Parses the upper case letter used for the piece in algebraic notation (`P` for pawns).
+    pub fn from_char(c: char) -> Option<PieceKind> {
+        match c {
+            'K' => Some(PieceKind::King),
+            'Q' => Some(PieceKind::Queen),
+            'R' => Some(PieceKind::Rook),
+            'B' => Some(PieceKind::Bishop),
+            'N' => Some(PieceKind::Knight),
+            'P' => Some(PieceKind::Pawn),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        match self {
+            PieceKind::King => 'K',
+            PieceKind::Queen => 'Q',
+            PieceKind::Rook => 'R',
+            PieceKind::Bishop => 'B',
+            PieceKind::Knight => 'N',
+            PieceKind::Pawn => 'P',
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Color {
     Black,
     White,
 }
 
+impl Color {
+    pub fn opposite(&self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Piece {
     pub kind: PieceKind,
@@ -56,6 +93,43 @@ impl Piece {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct CastlingRights {
+    pub white_king_side: bool,
+    pub white_queen_side: bool,
+    pub black_king_side: bool,
+    pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+    pub fn all() -> CastlingRights {
+        CastlingRights {
+            white_king_side: true,
+            white_queen_side: true,
+            black_king_side: true,
+            black_queen_side: true,
+        }
+    }
+
+    pub fn can_castle(&self, color: Color, king_side: bool) -> bool {
+        match (color, king_side) {
+            (Color::White, true) => self.white_king_side,
+            (Color::White, false) => self.white_queen_side,
+            (Color::Black, true) => self.black_king_side,
+            (Color::Black, false) => self.black_queen_side,
+        }
+    }
+
+    pub fn revoke(&mut self, color: Color, king_side: bool) {
+        match (color, king_side) {
+            (Color::White, true) => self.white_king_side = false,
+            (Color::White, false) => self.white_queen_side = false,
+            (Color::Black, true) => self.black_king_side = false,
+            (Color::Black, false) => self.black_queen_side = false,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct Position {
     pub row: i32,
@@ -68,20 +142,36 @@ impl Position {
     }
 
     pub fn add(p1: &Position, p2: &Position) -> Position {
-        Position::new(p1.row.clone() + p2.row.clone(), p1.col.clone() + p2.col.clone())
+        Position::new(p1.row + p2.row, p1.col + p2.col)
+    }
+
+    /// The lower case square name used by FEN and algebraic notation, e.g. `e4`.
+    pub fn to_algebraic(&self) -> String {
+        let file = std::char::from_u32((self.col + 97) as u32).unwrap();
+        format!("{}{}", file, self.row + 1)
+    }
+
+    pub fn from_algebraic(name: &str) -> Option<Position> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(file @ 'a'..='h'), Some(rank @ '1'..='8'), None) => {
+                Some(Position::new(rank as i32 - '1' as i32, file as i32 - 'a' as i32))
+            }
+            _ => None,
+        }
     }
 
     pub fn yield_all_inverse_positions(&self) -> Vec<Position> {
-        let scalars = vec![-1, 1];
+        let scalars = [-1, 1];
         let mut set = HashSet::new();
         for i in scalars.iter() {
             for j in scalars.iter() {
-                let row = i * self.row.clone();
-                let col = j * self.col.clone();
+                let row = i * self.row;
+                let col = j * self.col;
                 set.insert(Position::new(row, col));
             }
         }
-        set.iter().map(|x| x.clone()).collect()
+        set.iter().copied().collect()
     }
 }
 
@@ -89,16 +179,33 @@ impl Position {
 pub struct Board {
     size: i32,
     board: HashMap<Position, Piece>,
+    turn: Color,
+    en_passant: Option<Position>,
+    castling: CastlingRights,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    /// Zobrist hash of everything above bar the clocks, kept up to date by each setter.
+    hash: u64,
+    /// The same pieces as `board`, for the engine's attack and move generation.
+    bitboards: Bitboards,
 }
 
 impl Board {
     pub fn new(size: i32) -> Result<Board, String> {
-        if size < 0 {
-            Err("cannot have a negative size".to_string())
+        // the bitboards have a bit for each square of an 8x8 board, and no room for more
+        if size != 8 {
+            Err(format!("cannot have a size of {}, only 8", size))
         } else {
             Ok(Board {
-                size: size,
+                size,
                 board: HashMap::new(),
+                turn: Color::White,
+                en_passant: None,
+                castling: CastlingRights::default(),
+                halfmove_clock: 0,
+                fullmove_number: 1,
+                hash: 0,
+                bitboards: Bitboards::default(),
             })
         }
     }
@@ -119,18 +226,15 @@ impl Board {
                     }
                 }
             },
-            None => match column {
-                Some(c_index) => {
-                    for i in 0..self.size {
-                        update_positions.push(Position::new(i, c_index));
-                        update_positions.push(Position::new(i, c_index));
-                    }
+            None => if let Some(c_index) = column {
+                for i in 0..self.size {
+                    update_positions.push(Position::new(i, c_index));
+                    update_positions.push(Position::new(i, c_index));
                 }
-                None => (),
             },
         }
         for pos in update_positions.iter() {
-            self.set_space(pos, Some(piece.clone()))?;
+            self.set_space(pos, Some(piece))?;
         }
         Ok(())
     }
@@ -143,29 +247,33 @@ impl Board {
     }
 
     pub fn move_piece(&mut self, from: &Position, to: &Position) -> Result<Option<Piece>, String> {
-        match self.board.remove(from) {
+        match self.set_space(from, None)? {
             Option::None => Err("The from space does not contain a piece to move".to_string()),
             Option::Some(mut from_piece) => {
                 from_piece.has_moved = true;
-                let res = match self.set_space(to, Some(from_piece))? {
+                match self.set_space(to, Some(from_piece))? {
                     Option::None => Ok(Option::None),
                     Option::Some(to_piece) => Ok(Option::Some(to_piece))
-                };
-                res
+                }
             }
         }
     }
 
+    pub fn place_piece(&mut self, p: &Position, piece: Piece) -> Result<Option<Piece>, String> {
+        self.set_space(p, Some(piece))
+    }
+
+    pub fn remove_piece(&mut self, p: &Position) -> Result<Option<Piece>, String> {
+        self.set_space(p, None)
+    }
+
     pub fn get_space(&self, p: &Position) -> Result<Option<&Piece>, String> {
         self.validate_position(p)?;
         Ok(self.board.get(p))
     }
 
     pub fn is_empty_space(&self, p: &Position) -> bool {
-        match self.get_space(p) {
-            Ok(Option::None) => true,
-            _ => false,
-        }
+        matches!(self.get_space(p), Ok(Option::None))
     }
 
     pub fn pretty(&self) -> String {
@@ -198,16 +306,83 @@ impl Board {
         self.size
     }
 
+    pub fn get_turn(&self) -> Color {
+        self.turn
+    }
+
+    pub fn set_turn(&mut self, color: Color) {
+        if color != self.turn {
+            self.hash ^= zobrist::side_key();
+        }
+        self.turn = color;
+    }
+
+    /// The square skipped over by a pawn's double push on the previous move, if any.
+    pub fn get_en_passant(&self) -> Option<Position> {
+        self.en_passant
+    }
+
+    pub fn set_en_passant(&mut self, target: Option<Position>) {
+        self.hash ^= zobrist::en_passant_key(self.en_passant) ^ zobrist::en_passant_key(target);
+        self.en_passant = target;
+    }
+
+    pub fn get_castling_rights(&self) -> CastlingRights {
+        self.castling
+    }
+
+    pub fn set_castling_rights(&mut self, rights: CastlingRights) {
+        self.hash ^= zobrist::castling_key(&self.castling) ^ zobrist::castling_key(&rights);
+        self.castling = rights;
+    }
+
+    /// Half moves since the last capture or pawn move, for the fifty-move rule.
+    pub fn get_halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn set_halfmove_clock(&mut self, clock: u32) {
+        self.halfmove_clock = clock;
+    }
+
+    /// Starts at 1 and goes up after each of Black's moves.
+    pub fn get_fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn set_fullmove_number(&mut self, number: u32) {
+        self.fullmove_number = number;
+    }
+
+    /// Identifies the position for the search; boards that differ only in their clocks or in
+    /// which pieces have moved share a hash.
+    pub fn get_hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn get_bitboards(&self) -> &Bitboards {
+        &self.bitboards
+    }
+
     fn get_chess_row_boarder_string(&self) -> String {
-        format!("{}", "------".repeat(self.size as usize))
+        "------".repeat(self.size as usize)
     }
 
     fn set_space(&mut self, p: &Position, piece: Option<Piece>) -> Result<Option<Piece>, String> {
         self.validate_position(p)?;
-        match piece {
-            Option::None => Ok(self.board.remove(p)),
-            Option::Some(piece) => Ok(self.board.insert(p.clone(), piece))
+        let previous = match piece {
+            Option::None => self.board.remove(p),
+            Option::Some(piece) => {
+                self.hash ^= zobrist::piece_key(&piece, p);
+                self.bitboards.toggle(&piece, bitboard::square(p));
+                self.board.insert(*p, piece)
+            }
+        };
+        if let Some(previous) = previous {
+            self.hash ^= zobrist::piece_key(&previous, p);
+            self.bitboards.toggle(&previous, bitboard::square(p));
         }
+        Ok(previous)
     }
 
     pub fn validate_position(&self, p: &Position) -> Result<(), String> {
@@ -255,4 +430,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_board_size() {
+        assert!(Board::new(8).is_ok());
+        assert!(Board::new(9).is_err());
+        assert!(Board::new(4).is_err());
+        assert!(Board::new(-1).is_err());
+    }
+
+    #[test]
+    fn test_bitboards_follow_pieces() -> Result<(), String> {
+        let mut b = Board::new(8)?;
+        let rook = Piece::new(PieceKind::Rook, Color::White);
+        b.place_piece(&Position::new(0, 0), rook)?;
+        b.place_piece(&Position::new(7, 0), Piece::new(PieceKind::Rook, Color::Black))?;
+        b.move_piece(&Position::new(0, 0), &Position::new(7, 0))?;
+        assert_eq!(b.get_bitboards().pieces(Color::White, PieceKind::Rook), 1 << 56);
+        assert_eq!(b.get_bitboards().color(Color::Black), 0);
+        b.remove_piece(&Position::new(7, 0))?;
+        assert_eq!(b.get_bitboards().occupied(), 0);
+        Ok(())
+    }
 }
@@ -1,64 +1,342 @@
-use super::board::{Board, Piece, Position};
-use super::engine::{ChessEngine, BoardSetup};
-use super::game_repository::GameRepository;
+use super::board::{Board, Color, Piece, PieceKind, Position};
+use super::engine::{self, ChessEngine, BoardSetup, EndReason, GameResult, GameStatus, Move, MoveKind, Outcome, SearchLimits, SearchStats};
+use super::evaluation::{Evaluator, TaperedEvaluator};
+use super::fen;
+use super::game_repository::{self, Game, GameRepository, MoveRecord};
+use super::pgn;
+use super::san;
+use super::uci;
 use super::errors::GameError;
 
+use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_SEARCH_DEPTH: u32 = 3;
+pub const MAX_SEARCH_DEPTH: u32 = 10;
+/// The longest a search may take, whatever depth or time it was asked for.
+pub const MAX_SEARCH_TIME_MS: u64 = 10_000;
 
 pub struct GameController {
-    game_repository: RwLock<GameRepository>,
+    game_repository: Box<dyn GameRepository>,
+    evaluator: Arc<dyn Evaluator>,
+}
+
+#[derive(Serialize)]
+struct GameView {
+    /// Moves played so far, which a move can be sent along with to make sure it is played in
+    /// the position it was chosen for.
+    ply: usize,
+    fen: String,
+    turn: Color,
+    status: GameStatus,
+    result: Option<GameResult>,
+    /// Pieces by the API's square names, as in `MoveView`, unlike the `fen`.
+    board: HashMap<String, Piece>,
+}
+
+/// A move as the API shows it. `from` and `to` are the API's own square names, which count
+/// ranks down from row 0 as rank 8, so White's first rank is `A8`, and are not the algebraic
+/// names `san`, `pv` and FEN use. `uci` names the same move algebraically, such as `e2e4`.
+#[derive(Serialize)]
+struct MoveView {
+    from: String,
+    to: String,
+    uci: String,
+    kind: MoveKind,
+    captured: Option<PieceKind>,
+    promotion: Option<PieceKind>,
+}
+
+impl MoveView {
+    fn new(board_size: i32, mv: &Move) -> MoveView {
+        MoveView {
+            from: GameController::convert_position_to_space_name(board_size, &mv.from),
+            to: GameController::convert_position_to_space_name(board_size, &mv.to),
+            uci: uci::move_to_uci(mv),
+            kind: mv.kind,
+            captured: mv.captured,
+            promotion: mv.promotion,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BestMoveView {
+    #[serde(flatten)]
+    mv: MoveView,
+    san: String,
+    score: i32,
+    /// Moves until mate, negative when the side asking is the one getting mated.
+    mate: Option<i32>,
+    pv: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BestMovesView {
+    stats: Option<SearchStats>,
+    moves: Vec<BestMoveView>,
 }
 
 impl GameController {
 
-    pub fn new(game_repository: GameRepository) -> GameController {
-        GameController {game_repository: RwLock::new(game_repository)}
+    pub fn new(game_repository: Box<dyn GameRepository>) -> GameController {
+        GameController::with_evaluator(game_repository, Arc::new(TaperedEvaluator::default()))
     }
 
-    pub fn start_game(&self) -> Result<String, String> {
-        let board = match ChessEngine::create_board(BoardSetup::Basic) {
-            Ok(b) => b,
-            Err(msg) => return Err(msg)
+    /// A controller whose best moves are scored by `evaluator`.
+    pub fn with_evaluator(game_repository: Box<dyn GameRepository>, evaluator: Arc<dyn Evaluator>) -> GameController {
+        GameController {game_repository, evaluator}
+    }
+
+    pub fn start_game(&self, fen: Option<String>) -> Result<String, GameError> {
+        let board = match fen {
+            Some(fen) => ChessEngine::create_board(BoardSetup::Fen(fen)).map_err(GameError::InvalidInput)?,
+            None => ChessEngine::create_board(BoardSetup::Basic).map_err(GameError::Internal)?,
         };
-        board.pretty_print();
-        let game_repo = &mut *self.game_repository.write().unwrap();
-        match game_repo.create_game(board) {
-            Ok(id) => Ok(id.to_string()),
-            Err(msg) => Err(msg),
+        let result = ChessEngine::game_result(&board);
+        let game_repo = &*self.game_repository;
+        let id = game_repo.create_game(board)?;
+        if let Some(result) = result {
+            game_repo.finish_game(id, result)?;
         }
+        Ok(id.to_string())
     }
 
-    pub fn get_game(&self, id: u32) -> Result<String, GameError> {
-        let game_repo: &GameRepository = & *self.game_repository.read().unwrap();
-        let board = game_repo.get_latest_game_board(id)?;
-        match serde_json::to_string(&GameController::get_board_external_rep(&board)) {
-            Ok(board_repr) => Ok(board_repr),
+    /// The game as JSON, along with its ETag, which changes whenever a move is played.
+    pub fn get_game(&self, id: u32) -> Result<(String, String), GameError> {
+        let game_repo = &*self.game_repository;
+        // one read, so that the ply and the ETag always go with the board
+        let game = game_repo.get_game(id)?;
+        let ply = game.get_moves().len();
+        let board = game.get_board();
+        let view = GameView {
+            ply,
+            fen: fen::to_fen(board),
+            turn: board.get_turn(),
+            status: ChessEngine::game_status(board),
+            result: game.get_result(),
+            board: GameController::get_board_external_rep(board),
+        };
+        match serde_json::to_string(&view) {
+            Ok(board_repr) => Ok((board_repr, GameController::etag(ply))),
             Err(msg) => Err(GameError::Internal(msg.to_string()))
         }
     }
 
-    pub fn play_move(&self, id: u32, src: String,
-                     dest: String) -> Result<(), GameError> {
-        // validate
-        let possible_moves = self.get_piece_move_options_helper(id, &src)?;
-        let allowed = possible_moves.iter().any(|m| *m == dest);
-        if !allowed {
-            return Err(GameError::NotAllowed);
+    pub fn etag(ply: usize) -> String {
+        format!("\"{}\"", ply)
+    }
+
+    /// The plies the ETags in an If-Match header were given for, or `None` for `*`, which
+    /// matches any. If-Match compares ETags strongly, so weak ones never match and are left out.
+    pub fn plies_from_if_match(if_match: &str) -> Result<Option<Vec<usize>>, GameError> {
+        if if_match.trim() == "*" {
+            return Ok(None);
+        }
+        let mut plies = vec![];
+        for etag in if_match.split(',').map(str::trim) {
+            if etag.starts_with("W/") {
+                continue;
+            }
+            let ply = etag.strip_prefix('"').and_then(|etag| etag.strip_suffix('"'))
+                .and_then(|ply| ply.parse::<usize>().ok())
+                .ok_or_else(|| GameError::InvalidInput(format!("Invalid ETag '{}'", etag)))?;
+            plies.push(ply);
         }
+        Ok(Some(plies))
+    }
 
-        // execute move
-        let game_repo: &GameRepository = & *self.game_repository.read().unwrap();
-        let mut board = game_repo.get_latest_game_board(id).unwrap();
-        let board_size = board.get_size();
-        let src_pos = GameController::convert_space_name_to_position(board_size, &src);
-        let dest_pos = GameController::convert_space_name_to_position(board_size, &dest);
-        ChessEngine::execute_move(&mut board, &src_pos, &dest_pos);
-        game_repo.update_game(id, &board)?;
-        Ok(())
+    /// Plays the piece on `src` to `dest`. With `expected_plies`, the move is only played if the
+    /// game has one of those numbers of moves, and is a `Conflict` otherwise.
+    pub fn play_move(&self, id: u32, src: String, dest: String, promotion: Option<String>,
+                     expected_plies: Option<&[usize]>) -> Result<(), GameError> {
+        let promotion = match promotion {
+            Some(letter) => Some(GameController::convert_letter_to_piece_kind(&letter)?),
+            None => None,
+        };
+        let game_repo = &*self.game_repository;
+        GameController::apply_move(game_repo, id, expected_plies, |board| {
+            let src_pos = GameController::convert_space_name_to_position(board.get_size(), &src)?;
+            let dest_pos = GameController::convert_space_name_to_position(board.get_size(), &dest)?;
+            // like the move options, only the side to move may move
+            match board.get_space(&src_pos) {
+                Ok(Some(piece)) if piece.color == board.get_turn() => (),
+                _ => return Err(GameError::NotAllowed),
+            }
+            ChessEngine::legal_moves(board, &src_pos).map_err(GameError::Internal)?
+                .into_iter()
+                .find(|mv| mv.to == dest_pos && mv.promotion == promotion)
+                .ok_or(GameError::NotAllowed)
+        })
+    }
+
+    /// Plays a move written in Standard Algebraic Notation, such as `Nf3` or `exd8=Q+`, with
+    /// `expected_plies` as for `play_move`.
+    pub fn play_san_move(&self, id: u32, san: &str, expected_plies: Option<&[usize]>) -> Result<(), GameError> {
+        let game_repo = &*self.game_repository;
+        GameController::apply_move(game_repo, id, expected_plies, |board| {
+            san::parse_san(board, san).map_err(GameError::InvalidInput)
+        })
+    }
+
+    // plays the move `choose` picks on the stored board, unless the game is already over or
+    // is at none of `expected_plies`; the move is chosen and played under the game's lock, so
+    // no other move can come in between
+    fn apply_move<F>(game_repo: &dyn GameRepository, id: u32, expected_plies: Option<&[usize]>,
+                     choose: F) -> Result<(), GameError>
+        where F: FnOnce(&Board) -> Result<Move, GameError> {
+        game_repo.update_game(id, Box::new(|board, ply| {
+            if expected_plies.is_some_and(|expected| !expected.contains(&ply)) {
+                return Err(GameError::Conflict);
+            }
+            let mv = choose(board)?;
+            let san = san::move_to_san(board, &mv).map_err(GameError::Internal)?;
+            let undo = ChessEngine::make_move(board, &mv).map_err(GameError::Internal)?;
+            Ok((undo, MoveRecord { mv: undo.mv, san }, ChessEngine::game_result(board)))
+        }))
+    }
+
+    pub fn get_game_pgn(&self, id: u32) -> Result<String, GameError> {
+        let game_repo = &*self.game_repository;
+        let game = game_repo.get_game(id)?;
+        let result = match game.get_result() {
+            Some(result) => result.outcome.as_str(),
+            None => "*",
+        };
+        let imported = |name: &str| {
+            game.get_tags().iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+        };
+        let mut tags: Vec<(String, String)> = pgn::SEVEN_TAG_ROSTER.iter()
+            .map(|name| {
+                let value = match *name {
+                    "Result" => result,
+                    "Date" => imported(name).unwrap_or_else(|| game.get_date()),
+                    _ => imported(name).unwrap_or("?"),
+                };
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        // the set up tags are written from the stored board rather than copied
+        tags.extend(game.get_tags().iter()
+            .filter(|(name, _)| !pgn::SEVEN_TAG_ROSTER.contains(&name.as_str()) && name != "SetUp" && name != "FEN")
+            .cloned());
+        let initial_fen = fen::to_fen(game.get_initial_board());
+        if initial_fen != fen::STARTING_FEN {
+            tags.push((String::from("SetUp"), String::from("1")));
+            tags.push((String::from("FEN"), initial_fen));
+        }
+        let moves: Vec<String> = game.get_moves().iter().map(|m| m.san.clone()).collect();
+        Ok(pgn::write_pgn(&tags, game.get_initial_board(), &moves, result))
+    }
+
+    /// Replays every game in `text` and stores them, returning their ids as JSON. Nothing is
+    /// stored unless all of the games are legal, and each game is stored whole or not at all.
+    pub fn import_pgn(&self, text: &str) -> Result<String, GameError> {
+        let games = pgn::parse_pgn(text).map_err(GameError::InvalidInput)?;
+        if games.is_empty() {
+            return Err(GameError::InvalidInput(String::from("No games found in PGN")));
+        }
+        let mut replays = vec![];
+        for (i, game) in games.into_iter().enumerate() {
+            let replay = GameController::replay_pgn_game(game)
+                .map_err(|msg| GameError::InvalidInput(format!("Game {}: {}", i + 1, msg)))?;
+            replays.push(replay);
+        }
+
+        let game_repo = &*self.game_repository;
+        let mut ids = vec![];
+        for replay in replays {
+            ids.push(game_repo.import_game(replay)?);
+        }
+        serde_json::to_string(&ids).map_err(|err| GameError::Internal(err.to_string()))
+    }
+
+    // plays out a PGN game's main line on a board of its own, as a game ready to be stored
+    fn replay_pgn_game(game: pgn::PgnGame) -> Result<Game, String> {
+        let setup = match game.get_tag("FEN") {
+            Some(fen) => BoardSetup::Fen(fen.to_string()),
+            None => BoardSetup::Basic,
+        };
+        let initial = ChessEngine::create_board(setup)?;
+        let mut board = initial.clone();
+        let mut moves = vec![];
+        let mut history = vec![];
+        for pgn_move in game.moves.iter() {
+            let number = match board.get_turn() {
+                Color::White => format!("{}.", board.get_fullmove_number()),
+                Color::Black => format!("{}...", board.get_fullmove_number()),
+            };
+            if ChessEngine::game_result(&board).is_some() {
+                return Err(format!("move {} {}: The game is already over", number, pgn_move.san));
+            }
+            let mv = san::parse_san(&board, &pgn_move.san)
+                .map_err(|msg| format!("move {} {}: {}", number, pgn_move.san, msg))?;
+            let san = san::move_to_san(&board, &mv)?;
+            history.push(ChessEngine::make_move(&mut board, &mv)?);
+            moves.push(MoveRecord { mv, san });
+        }
+        let result = ChessEngine::game_result(&board).or_else(|| {
+            Outcome::from_pgn(&game.result).map(|outcome| GameResult { outcome, reason: EndReason::Unknown })
+        });
+        // the ID is only given once the game is stored
+        let mut replay = Game::resume(0, game_repository::today(), initial, moves, board, history);
+        replay.set_tags(game.tags);
+        replay.set_result(result);
+        Ok(replay)
+    }
+
+    /// Ranks the moves `color` can play, best first, along with how the search went. Given a
+    /// time limit the search goes as deep as it can in that time, and otherwise to `depth`,
+    /// which defaults to `DEFAULT_SEARCH_DEPTH`. Depths past `MAX_SEARCH_DEPTH` and times past
+    /// `MAX_SEARCH_TIME_MS` are cut down to them, and no search runs longer than the latter.
+    pub fn get_best_moves(&self, id: u32, color: &str, depth: Option<u32>,
+                          time_ms: Option<u64>) -> Result<String, GameError> {
+        let color = GameController::convert_name_to_color(color)?;
+        let depth = match depth {
+            Some(0) => return Err(GameError::NotAllowed),
+            Some(depth) => depth.min(MAX_SEARCH_DEPTH),
+            None if time_ms.is_some() => engine::MAX_DEPTH,
+            None => DEFAULT_SEARCH_DEPTH,
+        };
+        let game_repo = &*self.game_repository;
+        let board = game_repo.get_latest_game_board(id)?;
+        // like the move options, only the side to move has any
+        if game_repo.get_game_result(id)?.is_some() || board.get_turn() != color {
+            return serde_json::to_string(&BestMovesView { stats: None, moves: vec![] })
+                .map_err(|err| GameError::Internal(err.to_string()));
+        }
+        let time = Duration::from_millis(time_ms.map_or(MAX_SEARCH_TIME_MS, |ms| ms.min(MAX_SEARCH_TIME_MS)));
+        let limits = SearchLimits {
+            depth,
+            deadline: Instant::now().checked_add(time),
+            evaluator: self.evaluator.clone(),
+            ..SearchLimits::default()
+        };
+        let result = ChessEngine::best_moves(&board, &limits).map_err(GameError::Internal)?;
+
+        let mut moves = vec![];
+        for scored in result.moves {
+            let mut line = board.clone();
+            let mut pv = vec![];
+            for mv in scored.pv.iter() {
+                pv.push(san::move_to_san(&line, mv).map_err(GameError::Internal)?);
+                ChessEngine::execute_move(&mut line, mv).map_err(GameError::Internal)?;
+            }
+            moves.push(BestMoveView {
+                mv: MoveView::new(board.get_size(), &scored.mv),
+                san: pv[0].clone(),
+                score: scored.score,
+                mate: ChessEngine::mate_in(scored.score),
+                pv,
+            });
+        }
+        let view = BestMovesView { stats: Some(result.stats), moves };
+        serde_json::to_string(&view).map_err(|err| GameError::Internal(err.to_string()))
     }
 
-    pub fn get_piece_move_options(&self, id: u32, pos_str: &String) -> Result<String, GameError> {
+    pub fn get_piece_move_options(&self, id: u32, pos_str: &str) -> Result<String, GameError> {
 
         let moves = self.get_piece_move_options_helper(id, pos_str)?;
         match serde_json::to_string(&moves) {
@@ -67,15 +345,22 @@ impl GameController {
         }
     }
 
-    fn get_piece_move_options_helper(&self, id: u32, pos_str: &String) -> Result<Vec<String>, GameError>{
-        let game_repo: &GameRepository = & *self.game_repository.read().unwrap();
+    fn get_piece_move_options_helper(&self, id: u32, pos_str: &str) -> Result<Vec<MoveView>, GameError>{
+        let game_repo = &*self.game_repository;
         let board = game_repo.get_latest_game_board(id)?;
-        let size = board.get_size();
-        let position = GameController::convert_space_name_to_position(size, pos_str);
+        let position = GameController::convert_space_name_to_position(board.get_size(), pos_str)?;
+        if game_repo.get_game_result(id)?.is_some() {
+            return Ok(vec![]);
+        }
+        // only the side to move has options
+        match board.get_space(&position) {
+            Ok(Option::Some(piece)) if piece.color == board.get_turn() => (),
+            _ => return Ok(vec![]),
+        }
         Ok(
-            ChessEngine::possible_moves(&board, &position).unwrap()
+            ChessEngine::legal_moves(&board, &position).unwrap()
             .iter()
-            .map(|p| GameController::convert_position_to_space_name(size, p))
+            .map(|mv| MoveView::new(board.get_size(), mv))
             .collect()
         )
     }
@@ -83,7 +368,7 @@ impl GameController {
     pub fn get_board_external_rep(board: &Board) -> HashMap<String, Piece> {
         let size = board.get_size();
         board.get_piece_positions().iter()
-            .map(|(pos, piece)| (GameController::convert_position_to_space_name(size, pos), piece.clone()))
+            .map(|(pos, piece)| (GameController::convert_position_to_space_name(size, pos), *piece))
             .collect()
     }
 
@@ -93,10 +378,37 @@ impl GameController {
         format!("{}{}", letter, &digit.to_string())
     }
 
-    pub fn convert_space_name_to_position(board_size: i32,name: &String) -> Position {
-        let col = (name.chars().nth(0).unwrap() as u32) as i32 - 65;
-        let row = (board_size as i32) - name.chars().nth(1).unwrap().to_string().parse::<i32>().unwrap();
-        Position::new(row, col)
+    pub fn convert_letter_to_piece_kind(letter: &str) -> Result<PieceKind, GameError> {
+        let mut chars = letter.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => PieceKind::from_char(c.to_ascii_uppercase()).ok_or(GameError::NotAllowed),
+            _ => Err(GameError::NotAllowed),
+        }
+    }
+
+    pub fn convert_name_to_color(name: &str) -> Result<Color, GameError> {
+        match name.to_ascii_lowercase().as_str() {
+            "white" | "w" => Ok(Color::White),
+            "black" | "b" => Ok(Color::Black),
+            _ => Err(GameError::NotAllowed),
+        }
+    }
+
+    pub fn convert_space_name_to_position(board_size: i32, name: &str) -> Result<Position, GameError> {
+        let mut chars = name.chars();
+        let letter = match chars.next() {
+            Some(c) if c.is_ascii_uppercase() => c,
+            _ => return Err(GameError::NotAllowed),
+        };
+        let digit = match chars.as_str().parse::<i32>() {
+            Ok(d) if d >= 1 && d <= board_size => d,
+            _ => return Err(GameError::NotAllowed),
+        };
+        let col = letter as i32 - 65;
+        if col >= board_size {
+            return Err(GameError::NotAllowed);
+        }
+        Ok(Position::new(board_size - digit, col))
     }
 }
 
@@ -104,11 +416,16 @@ impl GameController {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::event_log_repository::EventLogGameRepository;
+    use super::super::game_repository::InMemoryGameRepository;
+    use super::super::sqlite_repository::SqliteGameRepository;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
 
 
     #[test]
     fn test_convert_position_to_space_name() -> Result<(), String> {
-        let mut b = Board::new(8)?;
+        let b = Board::new(8)?;
         let pos = Position::new(0, 1);
         assert_eq!(GameController::convert_position_to_space_name(b.get_size(), &pos),
                    String::from("B8"));
@@ -117,9 +434,9 @@ mod tests {
 
     #[test]
     fn test_convert_space_name_to_position() -> Result<(), String> {
-        let mut b = Board::new(8)?;
+        let b = Board::new(8)?;
         let pos_str = String::from("B8");
-        assert_eq!(GameController::convert_space_name_to_position(b.get_size(), &pos_str),
+        assert_eq!(GameController::convert_space_name_to_position(b.get_size(), &pos_str).unwrap(),
                    Position::new( 0, 1));
 
         Ok(())
@@ -127,11 +444,320 @@ mod tests {
 
     #[test]
     fn test_convert_space_name_to_position_0th() -> Result<(), String> {
-        let mut b = Board::new(8)?;
+        let b = Board::new(8)?;
         let pos_str = String::from("E0");
-        assert_eq!(GameController::convert_space_name_to_position(b.get_size(), &pos_str),
-                   Position::new( 0, 1));
+        assert!(GameController::convert_space_name_to_position(b.get_size(), &pos_str).is_err());
+        assert!(GameController::convert_space_name_to_position(b.get_size(), "I1").is_err());
+        assert!(GameController::convert_space_name_to_position(b.get_size(), "").is_err());
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_play_move_enforces_turn() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let black_pawn = String::from("B2");
+
+        assert_eq!(controller.get_piece_move_options(id, &black_pawn).unwrap(), "[]");
+        assert!(controller.play_move(id, black_pawn.clone(), String::from("B3"), None, None).is_err());
+        controller.play_move(id, String::from("B7"), String::from("B6"), None, None).unwrap();
+        assert!(controller.play_move(id, String::from("B6"), String::from("B5"), None, None).is_err());
+        controller.play_move(id, black_pawn, String::from("B3"), None, None).unwrap();
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        assert_eq!(game["turn"], "White");
+    }
+
+    #[test]
+    fn test_game_result_after_checkmate() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let moves = [("F7", "F6"), ("E2", "E4"), ("G7", "G5"), ("D1", "H5")];
+        for (src, dest) in moves.iter() {
+            controller.play_move(id, src.to_string(), dest.to_string(), None, None).unwrap();
+        }
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        assert_eq!(game["status"], "Checkmate");
+        assert_eq!(game["result"]["outcome"], "0-1");
+        assert_eq!(game["result"]["reason"], "Checkmate");
+        assert!(controller.play_move(id, String::from("A7"), String::from("A6"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_play_promotion() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let moves = [("H7", "H5"), ("G2", "G4"), ("H5", "G4"), ("H2", "H3"), ("G4", "H3"),
+            ("F1", "G2"), ("H3", "G2"), ("G1", "F3")];
+        for (src, dest) in moves.iter() {
+            controller.play_move(id, src.to_string(), dest.to_string(), None, None).unwrap();
+        }
+
+        let promote = |letter: Option<&str>| {
+            controller.play_move(id, String::from("G2"), String::from("H1"), letter.map(String::from), None)
+        };
+        assert!(promote(None).is_err());
+        assert!(promote(Some("K")).is_err());
+        assert!(promote(Some("X")).is_err());
+        promote(Some("n")).unwrap();
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        assert_eq!(game["board"]["H1"]["kind"], "Knight");
+        assert_eq!(game["board"]["H1"]["color"], "White");
+        assert!(controller.play_move(id, String::from("A2"), String::from("A3"), Some(String::from("Q")), None).is_err());
+    }
+
+    #[test]
+    fn test_piece_move_options() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let fen = "r3k3/1P6/8/3pP3/8/8/8/4K2R w K d6 0 1";
+        let id = controller.start_game(Some(fen.to_string())).unwrap().parse::<u32>().unwrap();
+        let options = |pos: &str| -> Vec<serde_json::Value> {
+            serde_json::from_str(&controller.get_piece_move_options(id, pos).unwrap()).unwrap()
+        };
+
+        let pawn = options("E4");
+        assert_eq!(pawn.len(), 2);
+        let en_passant = pawn.iter().find(|mv| mv["to"] == "D3").unwrap();
+        assert_eq!(en_passant["from"], "E4");
+        assert_eq!(en_passant["uci"], "e5d6");
+        assert_eq!(en_passant["kind"], "en_passant");
+        assert_eq!(en_passant["captured"], "Pawn");
+        assert!(options("E8").iter().any(|mv| mv["to"] == "G8" && mv["kind"] == "kingside_castle"));
+        let promotions = options("B2");
+        assert_eq!(promotions.len(), 8);
+        assert!(promotions.iter().any(|mv| mv["to"] == "A1" && mv["promotion"] == "Queen" && mv["captured"] == "Rook"));
+        assert!(promotions.iter().any(|mv| mv["uci"] == "b7a8q" && mv["captured"] == "Rook"));
+    }
+
+    #[test]
+    fn test_start_game_from_fen() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        let id = controller.start_game(Some(fen.to_string())).unwrap().parse::<u32>().unwrap();
+        controller.play_move(id, String::from("E8"), String::from("G8"), None, None).unwrap();
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        assert_eq!(game["fen"], "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+        assert_eq!(game["turn"], "Black");
+
+        match controller.start_game(Some(String::from("not a fen"))) {
+            Err(GameError::InvalidInput(_)) => (),
+            _ => panic!("expected the FEN to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_game_pgn() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let moves = [("E7", "E5"), ("E2", "E4"), ("G8", "F6"), ("B1", "C3")];
+        for (src, dest) in moves.iter() {
+            controller.play_move(id, src.to_string(), dest.to_string(), None, None).unwrap();
+        }
+        let pgn = controller.get_game_pgn(id).unwrap();
+        assert!(pgn.starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \""));
+        assert!(pgn.contains("[Result \"*\"]\n\n"));
+        assert!(pgn.ends_with("\n1. e4 e5 2. Nf3 Nc6 *\n"));
+
+        let id = controller.start_game(Some(String::from("4k3/8/8/8/8/8/8/R3K3 w - - 0 1")))
+            .unwrap().parse::<u32>().unwrap();
+        controller.play_move(id, String::from("A8"), String::from("A1"), None, None).unwrap();
+        let pgn = controller.get_game_pgn(id).unwrap();
+        assert!(pgn.contains("[Result \"*\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 w - - 0 1\"]\n"));
+        assert!(pgn.ends_with("\n1. Ra8+ *\n"));
+    }
+
+    #[test]
+    fn test_play_san_move() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        for san in ["e4", "e5", "Nf3", "Nc6", "Bc4", "Nf6", "O-O"].iter() {
+            controller.play_san_move(id, san, None).unwrap();
+        }
+        match controller.play_san_move(id, "Ke6", None) {
+            Err(GameError::InvalidInput(msg)) => assert_eq!(msg, "Illegal move 'Ke6'"),
+            _ => panic!("expected the move to be rejected"),
+        }
+        assert!(controller.play_san_move(id, "e4e5", None).is_err());
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        assert_eq!(game["fen"], "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 5 4");
+        assert!(controller.get_game_pgn(id).unwrap().ends_with("\n1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. O-O *\n"));
+
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        for san in ["f3", "e5", "g4", "Qh4#"].iter() {
+            controller.play_san_move(id, san, None).unwrap();
+        }
+        assert!(controller.play_san_move(id, "a3", None).is_err());
+    }
+
+    #[test]
+    fn test_get_best_moves() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1";
+        let id = controller.start_game(Some(fen.to_string())).unwrap().parse::<u32>().unwrap();
+
+        let best: serde_json::Value =
+            serde_json::from_str(&controller.get_best_moves(id, "White", Some(2), None).unwrap()).unwrap();
+        let moves = &best["moves"];
+        assert_eq!(moves[0]["from"], "A8");
+        assert_eq!(moves[0]["to"], "A1");
+        assert_eq!(moves[0]["uci"], "a1a8");
+        assert_eq!(moves[0]["san"], "Ra8#");
+        assert_eq!(moves[0]["kind"], "normal");
+        assert_eq!(moves[0]["captured"], serde_json::Value::Null);
+        assert_eq!(moves[0]["mate"], 1);
+        assert_eq!(moves[0]["pv"], serde_json::json!(["Ra8#"]));
+        assert_eq!(moves.as_array().unwrap().len(), 20);
+        assert_eq!(best["stats"]["depth"], 2);
+        assert!(best["stats"]["nodes"].as_u64().unwrap() > 20);
+
+        assert_eq!(controller.get_best_moves(id, "black", None, None).unwrap(), "{\"stats\":null,\"moves\":[]}");
+        assert!(controller.get_best_moves(id, "green", None, None).is_err());
+        assert!(controller.get_best_moves(id, "white", Some(0), None).is_err());
+        let best: serde_json::Value =
+            serde_json::from_str(&controller.get_best_moves(id, "white", Some(2), Some(u64::MAX)).unwrap()).unwrap();
+        assert_eq!(best["stats"]["depth"], 2);
+        let best: serde_json::Value =
+            serde_json::from_str(&controller.get_best_moves(id, "w", None, Some(200)).unwrap()).unwrap();
+        assert_eq!(best["moves"][0]["san"], "Ra8#");
+    }
+
+    #[test]
+    fn test_import_pgn() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let text = "[Event \"Casual\"]\n[White \"Anderssen\"]\n[Opening \"King's Pawn\"]\n\n\
+                    1. e4 e5 {A comment} 2. Nf3 (2. f4 exf4) 2... Nc6 3. Bb5 $2 a6 1-0\n\n\
+                    1. f3 e5 2. g4 Qh4# 0-1";
+        let ids: Vec<u32> = serde_json::from_str(&controller.import_pgn(text).unwrap()).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(ids[0]).unwrap().0).unwrap();
+        assert_eq!(game["fen"], "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4");
+        assert_eq!(game["result"]["outcome"], "1-0");
+        assert_eq!(game["result"]["reason"], "Unknown");
+        let pgn = controller.get_game_pgn(ids[0]).unwrap();
+        assert!(pgn.starts_with("[Event \"Casual\"]\n[Site \"?\"]\n"));
+        assert!(pgn.contains("[White \"Anderssen\"]\n"));
+        assert!(pgn.contains("[Result \"1-0\"]\n[Opening \"King's Pawn\"]\n\n"));
+        assert!(pgn.ends_with("\n1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0\n"));
+
+        let game: serde_json::Value = serde_json::from_str(&controller.get_game(ids[1]).unwrap().0).unwrap();
+        assert_eq!(game["result"]["reason"], "Checkmate");
+    }
+
+    #[test]
+    fn test_import_invalid_pgn() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        match controller.import_pgn("1. e4 e5 *\n\n1. e4 e5 2. Nf3 Nf3 *") {
+            Err(GameError::InvalidInput(msg)) => assert_eq!(msg, "Game 2: move 2... Nf3: Illegal move 'Nf3'"),
+            _ => panic!("expected the PGN to be rejected"),
+        }
+        assert!(controller.import_pgn("").is_err());
+        match controller.import_pgn("1. f3 e5 2. g4 Qh4# 3. a3 *") {
+            Err(GameError::InvalidInput(msg)) => assert_eq!(msg, "Game 1: move 3. a3: The game is already over"),
+            _ => panic!("expected the PGN to be rejected"),
+        }
+        assert!(controller.import_pgn("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n*").is_err());
+    }
+
+    #[test]
+    fn test_imported_games_survive_reopening() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let path = std::env::temp_dir().join(format!("chess-import-{}-{}", std::process::id(), nanos));
+        let path = path.to_str().unwrap().to_string();
+        let database = format!("{}.db", path);
+        let open: [&dyn Fn() -> Box<dyn GameRepository>; 2] = [
+            &|| Box::new(SqliteGameRepository::open(&database).unwrap()),
+            &|| Box::new(EventLogGameRepository::open(&path).unwrap()),
+        ];
+        for open in open.iter() {
+            let controller = GameController::new(open());
+            let ids: Vec<u32> = serde_json::from_str(&controller.import_pgn("1. e4 e5 2. Nf3 *").unwrap()).unwrap();
+
+            let controller = GameController::new(open());
+            controller.play_san_move(ids[0], "Nc6", None).unwrap();
+            let game: serde_json::Value = serde_json::from_str(&controller.get_game(ids[0]).unwrap().0).unwrap();
+            assert_eq!(game["fen"], "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+            assert!(controller.get_game_pgn(ids[0]).unwrap().ends_with("\n1. e4 e5 2. Nf3 Nc6 *\n"));
+        }
+        let _ = std::fs::remove_file(&database);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    // many threads playing the knights out and back in one game: every move must be checked
+    // against the position it is played in, so exactly the successful moves end up in the game
+    fn hammer_one_game(controller: &GameController) {
+        let cycle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let played = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let played = &played;
+                scope.spawn(move || {
+                    for attempt in 0..24 {
+                        if controller.play_san_move(id, cycle[(thread + attempt) % 4], None).is_ok() {
+                            played.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+
+        let played = played.load(Ordering::SeqCst);
+        assert!(played > 0);
+        let game = controller.game_repository.get_game(id).unwrap();
+        assert_eq!(game.get_moves().len(), played);
+        for (ply, record) in game.get_moves().iter().enumerate() {
+            assert_eq!(record.san, cycle[ply % 4]);
+        }
+        let view: serde_json::Value = serde_json::from_str(&controller.get_game(id).unwrap().0).unwrap();
+        let fen = view["fen"].as_str().unwrap().to_string();
+        assert!(fen.ends_with(&format!(" {} {}", played, 1 + played / 2)), "{}", fen);
+    }
+
+    #[test]
+    fn test_concurrent_moves() {
+        hammer_one_game(&GameController::new(Box::new(InMemoryGameRepository::new())));
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let path = std::env::temp_dir().join(format!("chess-concurrent-{}-{}", std::process::id(), nanos));
+        let path = path.to_str().unwrap().to_string();
+        let database = format!("{}.db", path);
+        hammer_one_game(&GameController::new(Box::new(SqliteGameRepository::open(&database).unwrap())));
+        hammer_one_game(&GameController::new(Box::new(EventLogGameRepository::open(&path).unwrap())));
+        let _ = std::fs::remove_file(&database);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_moves_against_stale_positions() {
+        let controller = GameController::new(Box::new(InMemoryGameRepository::new()));
+        let id = controller.start_game(None).unwrap().parse::<u32>().unwrap();
+        let (game, etag) = controller.get_game(id).unwrap();
+        let game: serde_json::Value = serde_json::from_str(&game).unwrap();
+        assert_eq!(game["ply"], 0);
+        assert_eq!(etag, "\"0\"");
+
+        controller.play_san_move(id, "e4", Some(&[0])).unwrap();
+        // a second client that also saw the starting position
+        assert!(matches!(controller.play_move(id, String::from("D7"), String::from("D5"), None, Some(&[0])),
+                         Err(GameError::Conflict)));
+        assert!(matches!(controller.play_san_move(id, "e5", Some(&[0])), Err(GameError::Conflict)));
+        controller.play_move(id, String::from("E2"), String::from("E4"), None, Some(&[3, 1])).unwrap();
+
+        let (game, etag) = controller.get_game(id).unwrap();
+        let game: serde_json::Value = serde_json::from_str(&game).unwrap();
+        assert_eq!(game["ply"], 2);
+        assert_eq!(game["fen"], "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+        assert_eq!(GameController::plies_from_if_match(&etag).unwrap(), Some(vec![2]));
+        assert_eq!(GameController::plies_from_if_match("\"1\", W/\"2\",\"3\"").unwrap(), Some(vec![1, 3]));
+        assert_eq!(GameController::plies_from_if_match("W/\"2\"").unwrap(), Some(vec![]));
+        assert_eq!(GameController::plies_from_if_match(" * ").unwrap(), None);
+        assert!(GameController::plies_from_if_match("7").is_err());
+        assert!(matches!(controller.play_san_move(id, "Nf3", Some(&[])), Err(GameError::Conflict)));
+    }
+}
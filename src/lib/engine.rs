@@ -1,5 +1,13 @@
-use super::board::{Board, Color, Piece, PieceKind, Position};
+use super::bitboard::{self, Bitboard};
+use super::board::{Board, CastlingRights, Color, Piece, PieceKind, Position};
+use super::evaluation::{Evaluator, TaperedEvaluator};
+use super::fen;
+use super::transposition_table::{Bound, Entry, TranspositionTable};
+use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 struct MovePattern {
     is_repeatable: bool,
@@ -17,8 +25,259 @@ impl MovePattern {
 
 pub enum BoardSetup {
     Basic,
+    Fen(String),
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum GameStatus {
+    Ongoing,
+    Check,
+    Checkmate,
+    Stalemate,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Outcome {
+    #[serde(rename = "1-0")]
+    WhiteWins,
+    #[serde(rename = "0-1")]
+    BlackWins,
+    #[serde(rename = "1/2-1/2")]
+    Draw,
+}
+
+impl Outcome {
+    /// The result as written in PGN.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::WhiteWins => "1-0",
+            Outcome::BlackWins => "0-1",
+            Outcome::Draw => "1/2-1/2",
+        }
+    }
+
+    /// Reads a PGN result, which is `None` for an unfinished game.
+    pub fn from_pgn(result: &str) -> Option<Outcome> {
+        match result {
+            "1-0" => Some(Outcome::WhiteWins),
+            "0-1" => Some(Outcome::BlackWins),
+            "1/2-1/2" => Some(Outcome::Draw),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum EndReason {
+    Checkmate,
+    Stalemate,
+    /// Decided away from the board, such as a resignation recorded in an imported game.
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GameResult {
+    pub outcome: Outcome,
+    pub reason: EndReason,
+}
+
+/// The pieces a pawn may be promoted to, in order of preference.
+pub const PROMOTION_KINDS: [PieceKind; 4] =
+    [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
+
+/// Scores beyond `MATE_SCORE - MAX_PLY` are mates, found that many plies from the root.
+pub const MATE_SCORE: i32 = 100_000;
+const MAX_PLY: i32 = 1_000;
+/// The deepest a search goes when only its time is limited.
+pub const MAX_DEPTH: u32 = 64;
+
+/// The moves that follow rules of their own, besides moving one piece from square to square.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveKind {
+    Normal,
+    /// A pawn's first move, two squares forward, which allows en passant in reply.
+    DoublePawnPush,
+    EnPassant,
+    KingsideCastle,
+    QueensideCastle,
+    Promotion,
+}
+
+/// A move as the engine knows it: the squares, along with what kind of move it is and what
+/// it captures.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Move {
+    pub from: Position,
+    pub to: Position,
+    pub promotion: Option<PieceKind>,
+    pub kind: MoveKind,
+    /// The kind of piece taken, which is a pawn beside the destination for en passant.
+    pub captured: Option<PieceKind>,
+}
+
+impl Move {
+    /// A move given only by its squares, as a player enters it. `make_move` fills in the rest
+    /// from the board.
+    pub fn new(from: Position, to: Position, promotion: Option<PieceKind>) -> Move {
+        let kind = if promotion.is_some() { MoveKind::Promotion } else { MoveKind::Normal };
+        Move { from, to, promotion, kind, captured: None }
+    }
+
+    /// Whether both moves go between the same squares with the same promotion, whatever else
+    /// is known about them.
+    pub fn matches(&self, other: &Move) -> bool {
+        self.from == other.from && self.to == other.to && self.promotion == other.promotion
+    }
+
+    pub fn is_capture(&self) -> bool {
+        self.captured.is_some()
+    }
+
+    pub fn is_castle(&self) -> bool {
+        self.kind == MoveKind::KingsideCastle || self.kind == MoveKind::QueensideCastle
+    }
+}
+
+/// Everything `make_move` changed besides moving the pieces, so that `unmake_move` can take
+/// the move back without a copy of the board from before it.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Undo {
+    pub mv: Move,
+    /// The piece that moved, as it was before the move and before any promotion.
+    pub moved: Piece,
+    pub captured: Option<Piece>,
+    pub castling: CastlingRights,
+    pub en_passant: Option<Position>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Undo {
+    /// Where the captured piece stood, which is beside the destination for en passant.
+    pub fn captured_position(&self) -> Position {
+        if self.mv.kind == MoveKind::EnPassant {
+            Position::new(self.mv.from.row, self.mv.to.col)
+        } else {
+            self.mv.to
+        }
+    }
+}
+
+/// How the engine finds moves and attacks. Both backends agree on every position: the
+/// bitboard one is what the engine uses, and the mailbox one, which walks the board's map of
+/// pieces square by square, is kept to check it against and to measure it by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Mailbox,
+    Bitboard,
+}
+
+/// A move found by the search. `score` is in centipawns for the side making it, and `pv`
+/// is the line the search expects to follow, starting with the move itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoredMove {
+    pub mv: Move,
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+/// How long to search: to `depth` plies, unless the deadline passes or `stop` is set first.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    pub depth: u32,
+    pub deadline: Option<Instant>,
+    pub stop: Option<Arc<AtomicBool>>,
+    pub features: SearchFeatures,
+    /// Scores the positions where the search stops.
+    pub evaluator: Arc<dyn Evaluator>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            depth: 0,
+            deadline: None,
+            stop: None,
+            features: SearchFeatures::default(),
+            evaluator: Arc::new(TaperedEvaluator::default()),
+        }
+    }
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits { depth, ..SearchLimits::default() }
+    }
+
+    /// Searches as deep as `time` allows.
+    pub fn time(time: Duration) -> SearchLimits {
+        SearchLimits { depth: MAX_DEPTH, deadline: Some(Instant::now() + time), ..SearchLimits::default() }
+    }
+}
+
+/// The techniques the search uses beyond plain alpha-beta. They are all on by default, and
+/// only turned off to measure what each one is worth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchFeatures {
+    /// Keep searching captures and promotions past the last ply, so that the search does not
+    /// stop in the middle of an exchange.
+    pub quiescence: bool,
+    /// Try captures by their most valuable victim and least valuable attacker, leaving those
+    /// that lose material on the exchange until after the quiet moves.
+    pub capture_ordering: bool,
+    /// Try quiet moves that caused cutoffs elsewhere in the tree first.
+    pub killers_and_history: bool,
+}
+
+impl Default for SearchFeatures {
+    fn default() -> Self {
+        SearchFeatures { quiescence: true, capture_ordering: true, killers_and_history: true }
+    }
+}
+
+impl SearchFeatures {
+    pub fn none() -> SearchFeatures {
+        SearchFeatures { quiescence: false, capture_ordering: false, killers_and_history: false }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SearchStats {
+    /// The depth of the deepest iteration that finished.
+    pub depth: u32,
+    pub nodes: u64,
+    pub nps: u64,
+    pub time_ms: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub moves: Vec<ScoredMove>,
+    pub stats: SearchStats,
+}
+
+// the state of one search, shared by every node in it
+struct Search<'a> {
+    deadline: Option<Instant>,
+    stop: Option<Arc<AtomicBool>>,
+    cancellable: bool,
+    features: SearchFeatures,
+    evaluator: &'a dyn Evaluator,
+    nodes: u64,
+    table: &'a mut TranspositionTable,
+    // two quiet moves per ply that caused a cutoff there
+    killers: Vec<[Option<Move>; 2]>,
+    // how often a quiet move from one square to another has caused a cutoff, weighted by depth
+    history: Vec<i32>,
+}
+
+// move ordering bands, highest first
+const HASH_MOVE_ORDER: i64 = 1 << 50;
+const GOOD_CAPTURE_ORDER: i64 = 1 << 40;
+const KILLER_ORDER: i64 = 1 << 35;
+const BAD_CAPTURE_ORDER: i64 = -(1 << 40);
+
 pub struct ChessEngine {
     pub board: Board,
 }
@@ -27,18 +286,69 @@ impl ChessEngine {
 
     pub fn create_board(setup: BoardSetup) -> Result<Board, String> {
         match setup {
-            BoardSetup::Basic => ChessEngine::setup_basic_board()
+            BoardSetup::Basic => ChessEngine::setup_basic_board(),
+            BoardSetup::Fen(fen) => fen::parse_fen(&fen),
         }
     }
 
-    pub fn possible_moves(board: &Board, p: &Position) -> Result<Vec<Position>, String> {
+    /// The moves of the piece on `p`, without regard to its own king, with one move per
+    /// promotion choice.
+    pub fn possible_moves(board: &Board, p: &Position) -> Result<Vec<Move>, String> {
+        ChessEngine::possible_moves_with(board, p, Backend::Bitboard)
+    }
+
+    pub fn possible_moves_with(board: &Board, p: &Position, backend: Backend) -> Result<Vec<Move>, String> {
+        let piece = match board.get_space(p)? {
+            Option::None => return Ok(vec![]),
+            Option::Some(piece) => *piece,
+        };
+        let mut moves = vec![];
+        for to in ChessEngine::possible_targets(board, p, backend)? {
+            if ChessEngine::is_promotion(board, p, &to) {
+                moves.extend(PROMOTION_KINDS.iter().map(|kind| ChessEngine::describe_move(board, &piece, p, &to, Some(*kind))));
+            } else {
+                moves.push(ChessEngine::describe_move(board, &piece, p, &to, None));
+            }
+        }
+        Ok(moves)
+    }
+
+    // what kind of move `piece` makes from `from` to `to`, and what it takes
+    fn describe_move(board: &Board, piece: &Piece, from: &Position, to: &Position,
+                     promotion: Option<PieceKind>) -> Move {
+        let kind = match piece.kind {
+            _ if promotion.is_some() => MoveKind::Promotion,
+            PieceKind::King if to.col - from.col == 2 => MoveKind::KingsideCastle,
+            PieceKind::King if from.col - to.col == 2 => MoveKind::QueensideCastle,
+            PieceKind::Pawn if (to.row - from.row).abs() == 2 => MoveKind::DoublePawnPush,
+            PieceKind::Pawn if ChessEngine::is_en_passant(board, from, to) => MoveKind::EnPassant,
+            _ => MoveKind::Normal,
+        };
+        let captured = match board.get_space(to) {
+            Ok(Some(target)) => Some(target.kind),
+            _ if kind == MoveKind::EnPassant => Some(PieceKind::Pawn),
+            _ => None,
+        };
+        Move { from: *from, to: *to, promotion, kind, captured }
+    }
+
+    // the squares the piece on `p` could move to
+    fn possible_targets(board: &Board, p: &Position, backend: Backend) -> Result<Vec<Position>, String> {
+        if backend == Backend::Bitboard {
+            return Ok(match board.get_space(p)? {
+                Option::None => vec![],
+                Option::Some(piece) => bitboard::squares(ChessEngine::bitboard_targets(board, p, piece))
+                    .map(bitboard::position)
+                    .collect(),
+            });
+        }
         match board.get_space(p)? {
             Option::None => Ok(vec![]),
-            Option::Some(Piece { kind: PieceKind::Pawn, color: c, has_moved: hm }) => {
-                Ok(ChessEngine::generate_pawn_moves(board, p, *c, *hm))
+            Option::Some(Piece { kind: PieceKind::Pawn, color: c, .. }) => {
+                Ok(ChessEngine::generate_pawn_moves(board, p, *c))
             },
-            Option::Some(Piece { kind: PieceKind::King, color: c, has_moved: hm }) => {
-                Ok(ChessEngine::generate_king_moves(board, p, *c, *hm))
+            Option::Some(Piece { kind: PieceKind::King, color: c, .. }) => {
+                Ok(ChessEngine::generate_king_moves(board, p, *c))
             },
             Option::Some(piece) => {
                 let mut solutions = vec![];
@@ -52,67 +362,235 @@ impl ChessEngine {
         }
     }
 
-    fn generate_king_moves(board: &Board, p: &Position, color: Color, has_moved: bool)
-        -> Vec<Position> {
-        let mut solutions = vec![];
-        if !has_moved {
-            if ChessEngine::can_side_castle(board, p, true) {
-                solutions.push(Position::new(p.row, p.col - 3));
+    // the squares `piece` on `p` could move to, as `possible_targets` would list them
+    fn bitboard_targets(board: &Board, p: &Position, piece: &Piece) -> Bitboard {
+        let boards = board.get_bitboards();
+        let square = bitboard::square(p);
+        let own = boards.color(piece.color);
+        let occupied = boards.occupied();
+        match piece.kind {
+            PieceKind::Pawn => {
+                let direction = if piece.color == Color::White { 1 } else { -1 };
+                let start_row = if piece.color == Color::White { 1 } else { board.get_size() - 2 };
+                let mut targets = bitboard::pawn_attacks(square, piece.color) & boards.color(piece.color.opposite());
+                if let Some(target) = board.get_en_passant() {
+                    if piece.color == board.get_turn() {
+                        targets |= bitboard::pawn_attacks(square, piece.color) & bitboard::bit(&target);
+                    }
+                }
+                let single = Position::new(p.row + direction, p.col);
+                if board.validate_position(&single).is_ok() && occupied & bitboard::bit(&single) == 0 {
+                    targets |= bitboard::bit(&single);
+                    let double = Position::new(p.row + 2 * direction, p.col);
+                    if p.row == start_row && occupied & bitboard::bit(&double) == 0 {
+                        targets |= bitboard::bit(&double);
+                    }
+                }
+                targets
+            }
+            PieceKind::King => {
+                let mut targets = bitboard::king_attacks(square) & !own;
+                for king_side in [true, false].iter() {
+                    if ChessEngine::can_side_castle(board, p, piece.color, *king_side, Backend::Bitboard) {
+                        let direction = if *king_side { 1 } else { -1 };
+                        targets |= bitboard::bit(&Position::new(p.row, p.col + 2 * direction));
+                    }
+                }
+                targets
             }
-            if ChessEngine::can_side_castle(board, p, false) {
-                solutions.push(Position::new(p.row, p.col + 2));
+            kind => bitboard::attacks(kind, square, occupied) & !own,
+        }
+    }
+
+    /// Like `possible_moves`, but drops every move that would leave the mover's king attacked.
+    pub fn legal_moves(board: &Board, p: &Position) -> Result<Vec<Move>, String> {
+        let color = match board.get_space(p)? {
+            Option::None => return Ok(vec![]),
+            Option::Some(piece) => piece.color,
+        };
+        Ok(ChessEngine::possible_moves(board, p)?
+            .into_iter()
+            .filter(|mv| ChessEngine::is_king_safe_after(board, p, &mv.to, color, Backend::Bitboard))
+            .collect())
+    }
+
+    fn is_king_safe_after(board: &Board, from: &Position, to: &Position, color: Color, backend: Backend) -> bool {
+        if backend == Backend::Mailbox {
+            let mut after = board.clone();
+            return match ChessEngine::make_move_unchecked(&mut after, from, to, None) {
+                Ok(_) => !ChessEngine::in_check(&after, color, Backend::Mailbox),
+                Err(_) => false,
+            };
+        }
+        // only the bitboards need to change to see whether the king is attacked afterwards
+        let mut boards = *board.get_bitboards();
+        let piece = match board.get_space(from) {
+            Ok(Some(piece)) => piece,
+            _ => return false,
+        };
+        if let Ok(Some(captured)) = board.get_space(to) {
+            boards.toggle(captured, bitboard::square(to));
+        }
+        if ChessEngine::is_en_passant(board, from, to) {
+            let captured = Position::new(from.row, to.col);
+            if let Ok(Some(pawn)) = board.get_space(&captured) {
+                boards.toggle(pawn, bitboard::square(&captured));
+            }
+        }
+        boards.toggle(piece, bitboard::square(from));
+        boards.toggle(piece, bitboard::square(to));
+        if ChessEngine::is_castle(board, from, to) {
+            let rook_from = ChessEngine::get_castling_rook_position(board, color, to.col > from.col);
+            let rook_to = Position::new(from.row, (from.col + to.col) / 2);
+            let rook = Piece::new(PieceKind::Rook, color);
+            boards.toggle(&rook, bitboard::square(&rook_from));
+            boards.toggle(&rook, bitboard::square(&rook_to));
+        }
+        match boards.king(color) {
+            Some(king) => !boards.is_attacked(king, color.opposite()),
+            None => true,
+        }
+    }
+
+    /// Classifies the position from the point of view of the side to move.
+    pub fn game_status(board: &Board) -> GameStatus {
+        let color = board.get_turn();
+        let in_check = ChessEngine::is_in_check(board, color);
+        let can_move = board.get_piece_positions().iter()
+            .filter(|(_, piece)| piece.color == color)
+            .any(|(pos, _)| !ChessEngine::legal_moves(board, pos).unwrap().is_empty());
+        match (in_check, can_move) {
+            (true, true) => GameStatus::Check,
+            (true, false) => GameStatus::Checkmate,
+            (false, true) => GameStatus::Ongoing,
+            (false, false) => GameStatus::Stalemate,
+        }
+    }
+
+    /// The final result of the game, or `None` while it is still being played.
+    pub fn game_result(board: &Board) -> Option<GameResult> {
+        match ChessEngine::game_status(board) {
+            GameStatus::Checkmate => {
+                let outcome = match board.get_turn() {
+                    Color::White => Outcome::BlackWins,
+                    Color::Black => Outcome::WhiteWins,
+                };
+                Some(GameResult { outcome, reason: EndReason::Checkmate })
+            }
+            GameStatus::Stalemate => {
+                Some(GameResult { outcome: Outcome::Draw, reason: EndReason::Stalemate })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_in_check(board: &Board, color: Color) -> bool {
+        ChessEngine::in_check(board, color, Backend::Bitboard)
+    }
+
+    fn in_check(board: &Board, color: Color, backend: Backend) -> bool {
+        let king = match backend {
+            Backend::Mailbox => ChessEngine::find_king(board, color),
+            Backend::Bitboard => board.get_bitboards().king(color).map(bitboard::position),
+        };
+        match king {
+            Option::Some(king_pos) => ChessEngine::is_threatened(board, &king_pos, color, backend),
+            Option::None => false,
+        }
+    }
+
+    fn find_king(board: &Board, color: Color) -> Option<Position> {
+        board.get_piece_positions().iter()
+            .find(|(_, piece)| piece.kind == PieceKind::King && piece.color == color)
+            .map(|(pos, _)| *pos)
+    }
+
+    fn generate_king_moves(board: &Board, p: &Position, color: Color) -> Vec<Position> {
+        let mut solutions = vec![];
+        for king_side in [true, false].iter() {
+            if ChessEngine::can_side_castle(board, p, color, *king_side, Backend::Mailbox) {
+                let direction = if *king_side { 1 } else { -1 };
+                solutions.push(Position::new(p.row, p.col + 2 * direction));
             }
         }
         // do the basic case
-        let surroundings: Vec<Position> = ChessEngine::expand_with_inverses(vec![
-            Position::new(0, 1),
-            Position::new(1, 0),
-            Position::new(1, 1),
-        ])
+        let surroundings: Vec<Position> = ChessEngine::get_move_pattern(PieceKind::King).unwrap()
+            .move_enumerations
             .iter()
             .map(|pos| Position::add(p, pos))
             .collect();
 
         for pos in surroundings {
-            if let Ok(_) = board.get_space(&pos) {
-                if ChessEngine::is_enemy_space(board, &pos, color)
-                    || board.is_empty_space(&pos) {
-                    if !ChessEngine::is_threatened(board, &pos, color) {
-                        solutions.push(pos.clone());
-                    }
-                }
+            if ChessEngine::is_enemy_space(board, &pos, color) || board.is_empty_space(&pos) {
+                solutions.push(pos);
             }
         }
         solutions
     }
 
-    fn can_side_castle(board: &Board, king_pos: &Position, is_left: bool) -> bool {
-        let direction = if is_left { -1 } else { 1 };
-        let rook_col = if is_left { 0 } else { (board.get_size() as i32) - 1 };
-        let mut can_castle =
-            match board.get_space(&Position::new(king_pos.row, rook_col)){
-                Ok(Option::Some(Piece {kind: _, color: _, has_moved: false})) => true,
-                _ => false,
-            };
-        let mut i = king_pos.col;
-        while i > 0 && can_castle {
-            i = i + direction;
-            match board.get_space(&Position::new(king_pos.row, i)) {
-                Ok(Option::Some(_)) => can_castle = false,
-                _ => ()
+    // the king may not castle out of, through or into check, and everything between
+    // it and the rook must be empty
+    fn can_side_castle(board: &Board, king_pos: &Position, color: Color, king_side: bool,
+                       backend: Backend) -> bool {
+        let home = ChessEngine::get_castling_home(board, color);
+        if !board.get_castling_rights().can_castle(color, king_side) || *king_pos != home {
+            return false;
+        }
+        let rook_pos = ChessEngine::get_castling_rook_position(board, color, king_side);
+        let is_own_rook = matches!(
+            board.get_space(&rook_pos),
+            Ok(Some(Piece { kind: PieceKind::Rook, color: c, .. })) if *c == color
+        );
+        if !is_own_rook {
+            return false;
+        }
+        let (low, high) = if king_side { (home.col + 1, rook_pos.col) } else { (rook_pos.col + 1, home.col) };
+        if (low..high).any(|col| !board.is_empty_space(&Position::new(home.row, col))) {
+            return false;
+        }
+        let direction = if king_side { 1 } else { -1 };
+        (0..3).all(|step| {
+            let pos = Position::new(home.row, home.col + step * direction);
+            !ChessEngine::is_threatened(board, &pos, color, backend)
+        })
+    }
+
+    fn get_castling_home(board: &Board, color: Color) -> Position {
+        let row = if color == Color::White { 0 } else { board.get_size() - 1 };
+        Position::new(row, 4)
+    }
+
+    fn get_castling_rook_position(board: &Board, color: Color, king_side: bool) -> Position {
+        let home = ChessEngine::get_castling_home(board, color);
+        Position::new(home.row, if king_side { board.get_size() - 1 } else { 0 })
+    }
+
+    // a king move gives up both rights, and a rook leaving or being captured on its
+    // starting square gives up that side's
+    fn update_castling_rights(board: &mut Board, from: &Position, to: &Position) {
+        let mut rights = board.get_castling_rights();
+        for color in [Color::White, Color::Black].iter() {
+            let king_moved = *from == ChessEngine::get_castling_home(board, *color)
+                && matches!(board.get_space(from), Ok(Some(Piece { kind: PieceKind::King, .. })));
+            for king_side in [true, false].iter() {
+                let rook_pos = ChessEngine::get_castling_rook_position(board, *color, *king_side);
+                if king_moved || *from == rook_pos || *to == rook_pos {
+                    rights.revoke(*color, *king_side);
+                }
             }
         }
-        can_castle
+        board.set_castling_rights(rights);
     }
 
-    fn generate_pawn_moves(board: &Board, p: &Position, c: Color, has_moved: bool) -> Vec<Position>{
+    fn generate_pawn_moves(board: &Board, p: &Position, c: Color) -> Vec<Position>{
         // convert color to direction
         let mut solutions = vec![];
         let direction = if c == Color::White { 1 } else { -1 };
+        let start_row = if c == Color::White { 1 } else { board.get_size() - 2 };
         let forward_space = Position::new(direction + p.row, p.col);
         if let Ok(Option::None) = board.get_space(&forward_space) {
             solutions.push(forward_space);
-            if has_moved == false {
+            if p.row == start_row {
                 let double_forward_space = Position::new(2 * direction + p.row
                                                          , p.col);
                 if let Ok(Option::None) = board.get_space(&double_forward_space) {
@@ -130,6 +608,13 @@ impl ChessEngine {
         if ChessEngine::is_enemy_space(board, &negative_diagonal_space, c) {
             solutions.push(negative_diagonal_space);
         }
+        // en passant
+        if let Some(target) = board.get_en_passant() {
+            if c == board.get_turn() && target.row == p.row + direction
+                && (target.col - p.col).abs() == 1 {
+                solutions.push(target);
+            }
+        }
         solutions
     }
 
@@ -143,18 +628,348 @@ impl ChessEngine {
         }
     }
 
-    pub fn execute_move(board: &mut Board, from: &Position, to: &Position)
-                        -> Result<Option<Piece>, String> {
-        let possibilities = ChessEngine::possible_moves(board, from)?;
-        if possibilities.contains(to) {
-            // if castle, also move Rook
-            if ChessEngine::is_castle(board, from, to) {
-                ChessEngine::castle_rook(board, from, to)?;
+    /// Plays `mv` if it is legal, returning the piece it captured.
+    pub fn execute_move(board: &mut Board, mv: &Move) -> Result<Option<Piece>, String> {
+        Ok(ChessEngine::make_move(board, mv)?.captured)
+    }
+
+    /// Plays `mv` if it is legal, returning what `unmake_move` needs to take it back. Only the
+    /// squares and promotion of `mv` are looked at, and the undo holds the move as the board
+    /// describes it. The board is left as it was when the move is not allowed.
+    pub fn make_move(board: &mut Board, mv: &Move) -> Result<Undo, String> {
+        let (from, to, promotion) = (&mv.from, &mv.to, mv.promotion);
+        match board.get_space(from)? {
+            Option::Some(piece) if piece.color != board.get_turn() => {
+                return Err(String::from("It is not this piece's turn to move"));
+            }
+            _ => (),
+        }
+        let possibilities = ChessEngine::legal_moves(board, from)?;
+        if !possibilities.iter().any(|legal| legal.to == *to) {
+            return Err(String::from("You cannot move to this space"));
+        }
+        match (ChessEngine::is_promotion(board, from, to), promotion) {
+            (true, None) => return Err(String::from("A piece to promote to must be chosen")),
+            (true, Some(kind)) if !PROMOTION_KINDS.contains(&kind) => {
+                return Err(String::from("A pawn cannot be promoted to this piece"));
+            }
+            (false, Some(_)) => return Err(String::from("This move is not a promotion")),
+            _ => (),
+        }
+        let legal = possibilities.into_iter().find(|legal| legal.matches(mv))
+            .ok_or("You cannot move to this space")?;
+        ChessEngine::play_unchecked(board, &legal)
+    }
+
+    /// Takes back the move `undo` was made for, which must be the last one played on `board`.
+    pub fn unmake_move(board: &mut Board, undo: &Undo) -> Result<(), String> {
+        let mv = &undo.mv;
+        board.remove_piece(&mv.to)?;
+        board.place_piece(&mv.from, undo.moved)?;
+        if let Some(captured) = undo.captured {
+            board.place_piece(&undo.captured_position(), captured)?;
+        }
+        if mv.is_castle() {
+            let rook_from = Position::new(mv.from.row, (mv.from.col + mv.to.col) / 2);
+            let king_side = mv.kind == MoveKind::KingsideCastle;
+            let rook_to = ChessEngine::get_castling_rook_position(board, undo.moved.color, king_side);
+            let mut rook = board.remove_piece(&rook_from)?.ok_or("The castled rook is missing")?;
+            // a rook that could still castle had not moved
+            rook.has_moved = false;
+            board.place_piece(&rook_to, rook)?;
+        }
+        board.set_castling_rights(undo.castling);
+        board.set_en_passant(undo.en_passant);
+        board.set_halfmove_clock(undo.halfmove_clock);
+        board.set_fullmove_number(undo.fullmove_number);
+        board.set_turn(undo.moved.color);
+        Ok(())
+    }
+
+    /// Plays a move already known to be legal, as `make_move` described it, passing the turn
+    /// and updating the clocks. Nothing is checked, so this is for moves that were checked when
+    /// they were first played, such as stored ones being read back.
+    pub fn play_unchecked(board: &mut Board, mv: &Move) -> Result<Undo, String> {
+        let moved = *board.get_space(&mv.from)?.ok_or("The from space does not contain a piece to move")?;
+        let mut undo = Undo {
+            mv: *mv,
+            moved,
+            captured: None,
+            castling: board.get_castling_rights(),
+            en_passant: board.get_en_passant(),
+            halfmove_clock: board.get_halfmove_clock(),
+            fullmove_number: board.get_fullmove_number(),
+        };
+        undo.captured = ChessEngine::make_move_unchecked(board, &mv.from, &mv.to, mv.promotion)?;
+        if moved.kind == PieceKind::Pawn || undo.captured.is_some() {
+            board.set_halfmove_clock(0);
+        } else {
+            board.set_halfmove_clock(board.get_halfmove_clock() + 1);
+        }
+        if board.get_turn() == Color::Black {
+            board.set_fullmove_number(board.get_fullmove_number() + 1);
+        }
+        board.set_turn(board.get_turn().opposite());
+        Ok(undo)
+    }
+
+    /// Every legal move for the side to move, with one move per promotion choice.
+    pub fn all_legal_moves(board: &Board) -> Result<Vec<Move>, String> {
+        ChessEngine::all_legal_moves_with(board, Backend::Bitboard)
+    }
+
+    pub fn all_legal_moves_with(board: &Board, backend: Backend) -> Result<Vec<Move>, String> {
+        ChessEngine::collect_legal_moves(board, |_| true, backend)
+    }
+
+    /// Only the legal captures and promotions, which is all the quiescence search looks at.
+    pub fn legal_captures(board: &Board) -> Result<Vec<Move>, String> {
+        ChessEngine::collect_legal_moves(board, |mv| mv.is_capture() || mv.kind == MoveKind::Promotion,
+                                         Backend::Bitboard)
+    }
+
+    // filters before checking legality, as that is the expensive part
+    fn collect_legal_moves<F>(board: &Board, keep: F, backend: Backend) -> Result<Vec<Move>, String>
+        where F: Fn(&Move) -> bool {
+        let mut moves = vec![];
+        let color = board.get_turn();
+        for (from, piece) in board.get_piece_positions().iter() {
+            if piece.color != color {
+                continue;
+            }
+            for mv in ChessEngine::possible_moves_with(board, from, backend)? {
+                if keep(&mv) && ChessEngine::is_king_safe_after(board, from, &mv.to, color, backend) {
+                    moves.push(mv);
+                }
+            }
+        }
+        // the board's own order changes from one run to the next, and the search should not
+        moves.sort_by_key(|mv| (mv.from.row, mv.from.col, mv.to.row, mv.to.col,
+                                mv.promotion.map(|kind| PROMOTION_KINDS.iter().position(|k| *k == kind))));
+        Ok(moves)
+    }
+
+    /// Counts the leaf nodes of the move tree `depth` plies deep, for checking the move
+    /// generator against known totals.
+    pub fn perft(board: &Board, depth: u32) -> Result<u64, String> {
+        ChessEngine::perft_with(board, depth, Backend::Bitboard)
+    }
+
+    pub fn perft_with(board: &Board, depth: u32, backend: Backend) -> Result<u64, String> {
+        ChessEngine::count_leaves(&mut board.clone(), depth, backend)
+    }
+
+    /// `perft` split by the first move, which narrows down where a wrong total goes wrong.
+    pub fn divide(board: &Board, depth: u32) -> Result<Vec<(Move, u64)>, String> {
+        let mut board = board.clone();
+        let mut counts = vec![];
+        for mv in ChessEngine::all_legal_moves(&board)? {
+            let undo = ChessEngine::play_unchecked(&mut board, &mv)?;
+            let count = ChessEngine::count_leaves(&mut board, depth.saturating_sub(1), Backend::Bitboard);
+            ChessEngine::unmake_move(&mut board, &undo)?;
+            counts.push((mv, count?));
+        }
+        Ok(counts)
+    }
+
+    fn count_leaves(board: &mut Board, depth: u32, backend: Backend) -> Result<u64, String> {
+        let moves = ChessEngine::all_legal_moves_with(board, backend)?;
+        if depth <= 1 {
+            return Ok(if depth == 0 { 1 } else { moves.len() as u64 });
+        }
+        let mut nodes = 0;
+        for mv in moves {
+            let undo = ChessEngine::play_unchecked(board, &mv)?;
+            let count = ChessEngine::count_leaves(board, depth - 1, backend);
+            ChessEngine::unmake_move(board, &undo)?;
+            nodes += count?;
+        }
+        Ok(nodes)
+    }
+
+    /// Static exchange evaluation: the material the side moving wins, in centipawns, if both
+    /// sides keep recapturing on the square `mv` moves to with their least valuable piece for
+    /// as long as it pays. Pins are not taken into account.
+    pub fn static_exchange(board: &Board, mv: &Move) -> Result<i32, String> {
+        let exchange_value = |kind: PieceKind| match kind {
+            // trading the king away ends the exchange badly for whoever does it
+            PieceKind::King => 20_000,
+            kind => ChessEngine::piece_value(kind),
+        };
+        let mut board = board.clone();
+        let mover = *board.get_space(&mv.from)?.ok_or("The from space does not contain a piece to move")?;
+        let mut gains = vec![mv.captured.map_or(0, ChessEngine::piece_value)];
+        let mut on_square = match mv.promotion {
+            Some(kind) => {
+                gains[0] += ChessEngine::piece_value(kind) - ChessEngine::piece_value(PieceKind::Pawn);
+                Piece::new(kind, mover.color)
+            }
+            None => mover,
+        };
+        if mv.kind == MoveKind::EnPassant {
+            board.remove_piece(&Position::new(mv.from.row, mv.to.col))?;
+        }
+        board.remove_piece(&mv.from)?;
+        board.place_piece(&mv.to, on_square)?;
+        let mut side = mover.color.opposite();
+        // taking a piece off the board uncovers any slider lined up behind it
+        while let Some(attacker) = ChessEngine::least_valuable_attacker(&board, &mv.to, side) {
+            gains.push(exchange_value(on_square.kind) - gains[gains.len() - 1]);
+            on_square = *board.get_space(&attacker)?.unwrap();
+            board.remove_piece(&attacker)?;
+            board.place_piece(&mv.to, on_square)?;
+            side = side.opposite();
+        }
+        // each side may stop recapturing whenever carrying on would lose more
+        for i in (1..gains.len()).rev() {
+            gains[i - 1] = -((-gains[i - 1]).max(gains[i]));
+        }
+        Ok(gains[0])
+    }
+
+    // the cheapest piece of `color` attacking `pos`
+    fn least_valuable_attacker(board: &Board, pos: &Position, color: Color) -> Option<Position> {
+        let boards = board.get_bitboards();
+        let attackers = boards.attackers(bitboard::square(pos), color, boards.occupied());
+        let kinds = [PieceKind::Pawn, PieceKind::Knight, PieceKind::Bishop,
+                     PieceKind::Rook, PieceKind::Queen, PieceKind::King];
+        kinds.iter()
+            .find_map(|kind| bitboard::squares(attackers & boards.pieces(color, *kind)).next())
+            .map(bitboard::position)
+    }
+
+    /// Ranks every legal move for the side to move, best first, deepening the search one ply
+    /// at a time until `limits` runs out. The ranking comes from the deepest iteration that
+    /// finished, and is empty once the game is over.
+    pub fn best_moves(board: &Board, limits: &SearchLimits) -> Result<SearchResult, String> {
+        ChessEngine::best_moves_with_table(board, limits, &mut TranspositionTable::default())
+    }
+
+    /// Like `best_moves`, but keeps what it learns in `table`, which a later search of a
+    /// related position can make use of.
+    pub fn best_moves_with_table(board: &Board, limits: &SearchLimits,
+                                 table: &mut TranspositionTable) -> Result<SearchResult, String> {
+        let start = Instant::now();
+        let mut search = Search {
+            deadline: limits.deadline,
+            stop: limits.stop.clone(),
+            cancellable: false,
+            features: limits.features,
+            evaluator: limits.evaluator.as_ref(),
+            nodes: 0,
+            table,
+            killers: vec![],
+            history: vec![0; 64 * 64],
+        };
+        // the search plays its moves on this board and takes them back again
+        let mut board = board.clone();
+        let mut root_moves = search.order_moves(&board, ChessEngine::all_legal_moves(&board)?, None, 0);
+        let mut moves = vec![];
+        let mut completed = 0;
+        for depth in 1..=limits.depth.max(1) {
+            if root_moves.is_empty() {
+                break;
+            }
+            // the first iteration always finishes, so that there is a move to play
+            search.cancellable = depth > 1;
+            match search.search_root(&mut board, &root_moves, depth as i32)? {
+                Some(results) => moves = results,
+                None => break,
+            }
+            completed = depth;
+            // the next iteration tries the best moves first
+            root_moves = moves.iter().map(|result| result.mv).collect();
+            // a deeper search cannot find a quicker mate
+            if ChessEngine::mate_in(moves[0].score).is_some_and(|n| n > 0) {
+                break;
+            }
+        }
+        let elapsed = start.elapsed();
+        let nps = (search.nodes as u128 * 1_000_000_000 / elapsed.as_nanos().max(1)) as u64;
+        let stats = SearchStats { depth: completed, nodes: search.nodes, nps, time_ms: elapsed.as_millis() as u64 };
+        Ok(SearchResult { moves, stats })
+    }
+
+    /// The number of moves until mate when `score` is a mate score, negative when the side the
+    /// score is for is the one being mated.
+    pub fn mate_in(score: i32) -> Option<i32> {
+        let plies = MATE_SCORE - score.abs();
+        if plies > MAX_PLY {
+            None
+        } else if score > 0 {
+            Some((plies + 1) / 2)
+        } else {
+            Some(-(plies + 1) / 2)
+        }
+    }
+
+    /// The usual material values, in centipawns, as used to order moves.
+    pub fn piece_value(kind: PieceKind) -> i32 {
+        match kind {
+            PieceKind::Pawn => 100,
+            PieceKind::Knight => 320,
+            PieceKind::Bishop => 330,
+            PieceKind::Rook => 500,
+            PieceKind::Queen => 900,
+            PieceKind::King => 0,
+        }
+    }
+
+    /// The pieces the pawn on `from` may become by moving to `to`; empty unless that is a
+    /// legal promotion.
+    pub fn promotion_options(board: &Board, from: &Position, to: &Position)
+                             -> Result<Vec<PieceKind>, String> {
+        if ChessEngine::is_promotion(board, from, to)
+            && ChessEngine::legal_moves(board, from)?.iter().any(|mv| mv.to == *to) {
+            Ok(PROMOTION_KINDS.to_vec())
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    pub fn is_promotion(board: &Board, from: &Position, to: &Position) -> bool {
+        match board.get_space(from) {
+            Ok(Some(Piece { kind: PieceKind::Pawn, color, .. })) => {
+                let last_row = if *color == Color::White { board.get_size() - 1 } else { 0 };
+                to.row == last_row
             }
-            Ok(board.move_piece(from, to)?)
+            _ => false,
+        }
+    }
+
+    // moves the pieces without validating the move or passing the turn
+    fn make_move_unchecked(board: &mut Board, from: &Position, to: &Position,
+                           promotion: Option<PieceKind>) -> Result<Option<Piece>, String> {
+        // if castle, also move Rook
+        if ChessEngine::is_castle(board, from, to) {
+            ChessEngine::castle_rook(board, from, to)?;
+        }
+        ChessEngine::update_castling_rights(board, from, to);
+        let mut captured = None;
+        if ChessEngine::is_en_passant(board, from, to) {
+            captured = board.remove_piece(&Position::new(from.row, to.col))?;
+        }
+        // remember the skipped square after a double pawn push
+        let is_pawn = matches!(board.get_space(from)?, Some(Piece { kind: PieceKind::Pawn, .. }));
+        if is_pawn && (to.row - from.row).abs() == 2 {
+            board.set_en_passant(Some(Position::new((from.row + to.row) / 2, from.col)));
         } else {
-            Err(String::from("You cannot move to this space"))
+            board.set_en_passant(None);
         }
+        let captured = board.move_piece(from, to)?.or(captured);
+        if let Some(kind) = promotion {
+            let color = board.get_space(to)?.unwrap().color;
+            let mut piece = Piece::new(kind, color);
+            piece.has_moved = true;
+            board.place_piece(to, piece)?;
+        }
+        Ok(captured)
+    }
+
+    fn is_en_passant(board: &Board, from: &Position, to: &Position) -> bool {
+        board.get_en_passant() == Some(*to)
+            && from.col != to.col
+            && matches!(board.get_space(from), Ok(Some(Piece { kind: PieceKind::Pawn, .. })))
     }
 
     fn apply_move(
@@ -166,9 +981,7 @@ impl ChessEngine {
         repeat: bool,
     ) {
         let check_pos = Position::add(pos, diff);
-        println!("Checking Position");
-        println!("{:?}", check_pos);
-        if let Err(_) = board.validate_position(&check_pos) {
+        if board.validate_position(&check_pos).is_err() {
             return;
         }
         if let Ok(space) = board.get_space(&check_pos) {
@@ -190,7 +1003,15 @@ impl ChessEngine {
 
     fn get_move_pattern(kind: PieceKind) -> Result<MovePattern, String> {
         match kind {
-            PieceKind::King | PieceKind::Pawn => Err(String::from("not supported")),
+            PieceKind::Pawn => Err(String::from("not supported")),
+            PieceKind::King => {
+                let moves = ChessEngine::expand_with_inverses(vec![
+                    Position::new(0, 1),
+                    Position::new(1, 0),
+                    Position::new(1, 1),
+                ]);
+                Ok(MovePattern::new(false, moves))
+            }
             PieceKind::Queen => {
                 let moves = ChessEngine::expand_with_inverses(vec![
                     Position::new(0, 1),
@@ -235,20 +1056,50 @@ impl ChessEngine {
     }
 
     fn castle_rook(board: &mut Board, king_start: &Position, king_dest: &Position) -> Result<(), String> {
-        let is_right = king_start.col > king_dest.col;
-        let rook_from_col = if is_right {0} else {board.get_size() as i32};
-        let rook_to_col = if is_right {king_dest.col - 1} else {king_dest.col + 1};
-        ChessEngine::execute_move(board, &Position::new(king_start.row, rook_from_col),
-                          &Position::new(king_start.row, rook_to_col))?;
+        let king_side = king_dest.col > king_start.col;
+        let color = board.get_space(king_start)?.unwrap().color;
+        let rook_from = ChessEngine::get_castling_rook_position(board, color, king_side);
+        // the rook ends up on the square the king passed over
+        let rook_to = Position::new(king_start.row, (king_start.col + king_dest.col) / 2);
+        board.move_piece(&rook_from, &rook_to)?;
         Ok(())
     }
 
-    fn is_threatened(board: &Board, pos: &Position, color: Color) -> bool {
-        let threatened = board.get_piece_positions().iter()
-            .filter(|(_, piece)| piece.color != color)
-            .flat_map(|(position, _)| ChessEngine::possible_moves(board, position).unwrap())
-            .any(|possible_move| possible_move == *pos);
-        threatened
+    // is `pos` attacked by any piece of the opposite color?
+    fn is_threatened(board: &Board, pos: &Position, color: Color, backend: Backend) -> bool {
+        if backend == Backend::Bitboard {
+            return board.get_bitboards().is_attacked(bitboard::square(pos), color.opposite());
+        }
+        let is_enemy = |p: &Position, kinds: &[PieceKind]| match board.get_space(p) {
+            Ok(Option::Some(piece)) => piece.color != color && kinds.contains(&piece.kind),
+            _ => false,
+        };
+        // pawns attack diagonally towards the side they are moving to
+        let pawn_row = if color == Color::White { pos.row + 1 } else { pos.row - 1 };
+        if is_enemy(&Position::new(pawn_row, pos.col - 1), &[PieceKind::Pawn])
+            || is_enemy(&Position::new(pawn_row, pos.col + 1), &[PieceKind::Pawn]) {
+            return true;
+        }
+        // look outwards from `pos` with each piece's pattern for an enemy moving the same way
+        let attackers = [
+            (PieceKind::King, vec![PieceKind::King]),
+            (PieceKind::Knight, vec![PieceKind::Knight]),
+            (PieceKind::Rook, vec![PieceKind::Rook, PieceKind::Queen]),
+            (PieceKind::Bishop, vec![PieceKind::Bishop, PieceKind::Queen]),
+        ];
+        for (kind, kinds) in attackers.iter() {
+            let pattern = ChessEngine::get_move_pattern(*kind).unwrap();
+            for diff in pattern.move_enumerations.iter() {
+                let mut check_pos = Position::add(pos, diff);
+                while pattern.is_repeatable && board.is_empty_space(&check_pos) {
+                    check_pos = Position::add(&check_pos, diff);
+                }
+                if is_enemy(&check_pos, kinds) {
+                    return true;
+                }
+            }
+        }
+        false
     }
 
     fn setup_basic_board() -> Result<Board, String>{
@@ -329,13 +1180,245 @@ impl ChessEngine {
             Piece::new(PieceKind::King, Color::Black),
         );
         b.populate(map)?;
+        b.set_castling_rights(CastlingRights::all());
         Ok(b)
     }
 }
 
+impl<'a> Search<'a> {
+    // scores every root move with a full window, so that each score is exact and they can be
+    // ranked; `None` when the search was cut short
+    fn search_root(&mut self, board: &mut Board, root_moves: &[Move],
+                   depth: i32) -> Result<Option<Vec<ScoredMove>>, String> {
+        let mut results = vec![];
+        for mv in root_moves.iter() {
+            let undo = ChessEngine::play_unchecked(board, mv)?;
+            let mut line = vec![];
+            let result = self.alpha_beta(board, depth - 1, 1, -MATE_SCORE, MATE_SCORE, &mut line);
+            ChessEngine::unmake_move(board, &undo)?;
+            match result? {
+                Some(score) => {
+                    line.insert(0, *mv);
+                    results.push(ScoredMove { mv: *mv, score: -score, pv: line });
+                }
+                None => return Ok(None),
+            }
+        }
+        results.sort_by_key(|result| std::cmp::Reverse(result.score));
+        Ok(Some(results))
+    }
+
+    // negamax alpha-beta, scored for the side to move
+    fn alpha_beta(&mut self, board: &mut Board, depth: i32, ply: i32, mut alpha: i32, mut beta: i32,
+                  pv: &mut Vec<Move>) -> Result<Option<i32>, String> {
+        if depth <= 0 {
+            pv.clear();
+            return self.quiescence(board, ply, alpha, beta);
+        }
+        if self.is_cancelled() {
+            return Ok(None);
+        }
+        self.nodes += 1;
+
+        let key = board.get_hash();
+        let mut hash_move = None;
+        if let Some(entry) = self.table.probe(key) {
+            hash_move = entry.best_move;
+            if entry.depth >= depth {
+                let score = Search::score_from_table(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => {
+                        // the rest of the line is not kept, so the principal variation ends here
+                        pv.clear();
+                        pv.extend(entry.best_move);
+                        return Ok(Some(score.clamp(alpha, beta)));
+                    }
+                    Bound::Lower => alpha = alpha.max(score),
+                    Bound::Upper => beta = beta.min(score),
+                }
+                if alpha >= beta {
+                    pv.clear();
+                    pv.extend(entry.best_move);
+                    return Ok(Some(score));
+                }
+            }
+        }
+
+        let moves = ChessEngine::all_legal_moves(board)?;
+        if moves.is_empty() {
+            // mates found sooner score higher
+            let in_check = ChessEngine::is_in_check(board, board.get_turn());
+            return Ok(Some(if in_check { ply - MATE_SCORE } else { 0 }));
+        }
+        let original_alpha = alpha;
+        let mut best_move = None;
+        for mv in self.order_moves(board, moves, hash_move, ply) {
+            let undo = ChessEngine::play_unchecked(board, &mv)?;
+            let mut line = vec![];
+            let result = self.alpha_beta(board, depth - 1, ply + 1, -beta, -alpha, &mut line);
+            ChessEngine::unmake_move(board, &undo)?;
+            let score = match result? {
+                Some(score) => -score,
+                None => return Ok(None),
+            };
+            if score > alpha {
+                alpha = score;
+                best_move = Some(mv);
+                pv.clear();
+                pv.push(mv);
+                pv.append(&mut line);
+                if alpha >= beta {
+                    self.record_cutoff(&mv, depth, ply);
+                    break;
+                }
+            }
+        }
+        let bound = if alpha >= beta {
+            Bound::Lower
+        } else if alpha > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        let score = Search::score_to_table(alpha, ply);
+        self.table.store(Entry { key, depth, score, bound, best_move: best_move.or(hash_move) });
+        Ok(Some(alpha))
+    }
+
+    // searches captures and promotions until the position is quiet; the side to move can
+    // always decline them and stand on the static evaluation instead
+    fn quiescence(&mut self, board: &mut Board, ply: i32, mut alpha: i32,
+                  beta: i32) -> Result<Option<i32>, String> {
+        if self.is_cancelled() {
+            return Ok(None);
+        }
+        self.nodes += 1;
+        let stand_pat = self.evaluator.evaluate(board);
+        if !self.features.quiescence || stand_pat >= beta {
+            return Ok(Some(stand_pat.clamp(alpha, beta)));
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = vec![];
+        for mv in ChessEngine::legal_captures(board)? {
+            // under-promotions only matter in the main search
+            if mv.promotion.is_some_and(|kind| kind != PieceKind::Queen) {
+                continue;
+            }
+            if self.features.capture_ordering && ChessEngine::static_exchange(board, &mv)? < 0 {
+                continue;
+            }
+            moves.push(mv);
+        }
+        for mv in self.order_moves(board, moves, None, ply) {
+            let undo = ChessEngine::play_unchecked(board, &mv)?;
+            let result = self.quiescence(board, ply + 1, -beta, -alpha);
+            ChessEngine::unmake_move(board, &undo)?;
+            let score = match result? {
+                Some(score) => -score,
+                None => return Ok(None),
+            };
+            if score > alpha {
+                alpha = score;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        Ok(Some(alpha))
+    }
+
+    // the best move from an earlier search of the position, then winning and even captures,
+    // killers, the other quiet moves by their history, and finally losing captures
+    fn order_moves(&self, board: &Board, mut moves: Vec<Move>, hash_move: Option<Move>,
+                   ply: i32) -> Vec<Move> {
+        moves.sort_by_cached_key(|mv| std::cmp::Reverse(self.move_order(board, mv, hash_move, ply)));
+        moves
+    }
+
+    fn move_order(&self, board: &Board, mv: &Move, hash_move: Option<Move>, ply: i32) -> i64 {
+        if hash_move.is_some_and(|hash_move| hash_move.matches(mv)) {
+            return HASH_MOVE_ORDER;
+        }
+        if mv.is_capture() || mv.promotion.is_some() {
+            if !self.features.capture_ordering {
+                return 0;
+            }
+            let attacker = board.get_space(&mv.from).ok().flatten().map_or(PieceKind::Pawn, |piece| piece.kind);
+            let victim = mv.captured.map_or(0, ChessEngine::piece_value) + mv.promotion.map_or(0, ChessEngine::piece_value);
+            let mvv_lva = (victim * 10 - ChessEngine::piece_value(attacker)) as i64;
+            return match ChessEngine::static_exchange(board, mv) {
+                Ok(gain) if gain < 0 => BAD_CAPTURE_ORDER + mvv_lva,
+                _ => GOOD_CAPTURE_ORDER + mvv_lva,
+            };
+        }
+        if !self.features.killers_and_history {
+            return 0;
+        }
+        if self.killers.get(ply as usize).is_some_and(|killers| killers.iter().flatten().any(|killer| killer.matches(mv))) {
+            return KILLER_ORDER;
+        }
+        self.history[Search::history_index(mv)] as i64
+    }
+
+    fn record_cutoff(&mut self, mv: &Move, depth: i32, ply: i32) {
+        if !self.features.killers_and_history || mv.is_capture() || mv.promotion.is_some() {
+            return;
+        }
+        let ply = ply as usize;
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(*mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(*mv);
+        }
+        let index = Search::history_index(mv);
+        self.history[index] = self.history[index].saturating_add(depth * depth);
+    }
+
+    fn history_index(mv: &Move) -> usize {
+        ((mv.from.row * 8 + mv.from.col) * 64 + mv.to.row * 8 + mv.to.col) as usize
+    }
+
+    // mate scores count plies from the root, but the table is shared between positions at
+    // different plies, so it stores them counted from the position itself
+    fn score_to_table(score: i32, ply: i32) -> i32 {
+        if score > MATE_SCORE - MAX_PLY {
+            score + ply
+        } else if score < MAX_PLY - MATE_SCORE {
+            score - ply
+        } else {
+            score
+        }
+    }
+
+    fn score_from_table(score: i32, ply: i32) -> i32 {
+        if score > MATE_SCORE - MAX_PLY {
+            score - ply
+        } else if score < MAX_PLY - MATE_SCORE {
+            score + ply
+        } else {
+            score
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellable
+            && (self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                || self.stop.as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn destinations(board: &Board, p: &Position) -> Result<Vec<Position>, String> {
+        Ok(ChessEngine::legal_moves(board, p)?.iter().map(|mv| mv.to).collect())
+    }
+
     #[test]
     fn test_basic_board() -> Result<(), String> {
         let board = ChessEngine::setup_basic_board()?;
@@ -359,7 +1442,7 @@ mod tests {
         let mut board = ChessEngine::setup_basic_board()?;
         let from = Position::new(1, 1);
         let to = Position::new(2, 1);
-        ChessEngine::execute_move(&mut board, &from, &to)?;
+        ChessEngine::execute_move(&mut board, &Move::new(from, to, None))?;
 
         let op_to = board.get_space(&to)?;
         let op_from = board.get_space(&from)?;
@@ -372,6 +1455,482 @@ mod tests {
             op_to
         );
         assert_eq!(None, op_from);
+        assert_eq!(Color::Black, board.get_turn());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_out_of_turn() -> Result<(), String> {
+        let mut board = ChessEngine::setup_basic_board()?;
+        let black_pawn = Position::new(6, 1);
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(black_pawn, Position::new(5, 1), None)).is_err());
+
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 1), Position::new(2, 1), None))?;
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(Position::new(2, 1), Position::new(3, 1), None)).is_err());
+        ChessEngine::execute_move(&mut board, &Move::new(black_pawn, Position::new(5, 1), None))?;
+        assert_eq!(Color::White, board.get_turn());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legal_moves_respect_pins() -> Result<(), String> {
+        let mut board = Board::new(8)?;
+        let mut map = HashMap::new();
+        map.insert(Position::new(0, 4), Piece::new(PieceKind::King, Color::White));
+        map.insert(Position::new(1, 3), Piece::new(PieceKind::Bishop, Color::White));
+        map.insert(Position::new(3, 1), Piece::new(PieceKind::Bishop, Color::Black));
+        map.insert(Position::new(7, 4), Piece::new(PieceKind::King, Color::Black));
+        board.populate(map)?;
+
+        // the bishop may only move along the pin
+        let mut moves = destinations(&board, &Position::new(1, 3))?;
+        moves.sort_by_key(|p| p.row);
+        assert_eq!(moves, vec![Position::new(2, 2), Position::new(3, 1)]);
+        assert_eq!(ChessEngine::possible_moves(&board, &Position::new(1, 3))?.len(), 7);
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 3), Position::new(2, 4), None)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legal_moves_resolve_check() -> Result<(), String> {
+        let mut board = Board::new(8)?;
+        let mut map = HashMap::new();
+        map.insert(Position::new(0, 4), Piece::new(PieceKind::King, Color::White));
+        map.insert(Position::new(0, 0), Piece::new(PieceKind::Rook, Color::White));
+        map.insert(Position::new(5, 4), Piece::new(PieceKind::Rook, Color::Black));
+        map.insert(Position::new(7, 7), Piece::new(PieceKind::King, Color::Black));
+        board.populate(map)?;
+
+        assert!(ChessEngine::is_in_check(&board, Color::White));
+        // the rook can only block; it cannot ignore the check
+        assert!(destinations(&board, &Position::new(0, 0))?.is_empty());
+        // the king cannot step back along the checking file
+        let king_moves = destinations(&board, &Position::new(0, 4))?;
+        assert!(!king_moves.contains(&Position::new(1, 4)));
+        assert_eq!(king_moves.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkmate() -> Result<(), String> {
+        let mut board = ChessEngine::setup_basic_board()?;
+        assert_eq!(ChessEngine::game_status(&board), GameStatus::Ongoing);
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 5), Position::new(2, 5), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 4), Position::new(4, 4), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 6), Position::new(3, 6), None))?;
+        assert_eq!(ChessEngine::game_result(&board), None);
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(7, 3), Position::new(3, 7), None))?;
+
+        assert_eq!(ChessEngine::game_status(&board), GameStatus::Checkmate);
+        assert_eq!(ChessEngine::game_result(&board),
+                   Some(GameResult { outcome: Outcome::BlackWins, reason: EndReason::Checkmate }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_and_stalemate() -> Result<(), String> {
+        let mut board = Board::new(8)?;
+        let mut map = HashMap::new();
+        map.insert(Position::new(7, 7), Piece::new(PieceKind::King, Color::Black));
+        map.insert(Position::new(5, 6), Piece::new(PieceKind::Queen, Color::White));
+        map.insert(Position::new(0, 0), Piece::new(PieceKind::King, Color::White));
+        board.populate(map)?;
+        board.set_turn(Color::Black);
+        assert_eq!(ChessEngine::game_status(&board), GameStatus::Stalemate);
+        assert_eq!(ChessEngine::game_result(&board).unwrap().outcome, Outcome::Draw);
+
+        board.set_turn(Color::White);
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(5, 6), Position::new(5, 7), None))?;
+        assert_eq!(ChessEngine::game_status(&board), GameStatus::Check);
+        Ok(())
+    }
+
+    #[test]
+    fn test_en_passant() -> Result<(), String> {
+        let mut board = ChessEngine::setup_basic_board()?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 4), Position::new(3, 4), None))?;
+        assert_eq!(board.get_en_passant(), Some(Position::new(2, 4)));
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 0), Position::new(5, 0), None))?;
+        assert_eq!(board.get_en_passant(), None);
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(3, 4), Position::new(4, 4), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 3), Position::new(4, 3), None))?;
+
+        let white_pawn = Position::new(4, 4);
+        assert!(destinations(&board, &white_pawn)?.contains(&Position::new(5, 3)));
+        let captured = ChessEngine::execute_move(&mut board, &Move::new(white_pawn, Position::new(5, 3), None))?;
+        assert_eq!(captured, Some(Piece { kind: PieceKind::Pawn, color: Color::Black, has_moved: true }));
+        assert_eq!(board.get_space(&Position::new(4, 3))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_kinds() -> Result<(), String> {
+        let board = fen::parse_fen("r3k2r/1P6/8/3pP3/8/8/4P3/R3K2R w KQkq d6 0 1")?;
+        let kinds = |from: &str| -> Result<Vec<(String, MoveKind, Option<PieceKind>)>, String> {
+            let mut moves: Vec<_> = ChessEngine::legal_moves(&board, &Position::from_algebraic(from).unwrap())?
+                .iter().map(|mv| (mv.to.to_algebraic(), mv.kind, mv.captured)).collect();
+            moves.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(moves)
+        };
+        assert_eq!(kinds("e5")?, vec![(String::from("d6"), MoveKind::EnPassant, Some(PieceKind::Pawn)),
+                                      (String::from("e6"), MoveKind::Normal, None)]);
+        assert_eq!(kinds("e2")?, vec![(String::from("e3"), MoveKind::Normal, None),
+                                      (String::from("e4"), MoveKind::DoublePawnPush, None)]);
+        let king = kinds("e1")?;
+        assert!(king.contains(&(String::from("g1"), MoveKind::KingsideCastle, None)));
+        assert!(king.contains(&(String::from("c1"), MoveKind::QueensideCastle, None)));
+        let promotions = kinds("b7")?;
+        assert_eq!(promotions.len(), 8);
+        assert!(promotions.iter().all(|(_, kind, _)| *kind == MoveKind::Promotion));
+        assert_eq!(promotions.iter().filter(|(_, _, captured)| *captured == Some(PieceKind::Rook)).count(), 4);
+
+        // the move played is the one the board describes, whatever the caller knew about it
+        let mut board = board.clone();
+        let undo = ChessEngine::make_move(&mut board, &Move::new(Position::new(4, 4), Position::new(5, 3), None))?;
+        assert_eq!(undo.mv.kind, MoveKind::EnPassant);
+        assert_eq!(undo.captured_position(), Position::new(4, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_en_passant_expires() -> Result<(), String> {
+        let mut board = ChessEngine::setup_basic_board()?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 4), Position::new(3, 4), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 0), Position::new(5, 0), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(3, 4), Position::new(4, 4), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 3), Position::new(4, 3), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 0), Position::new(2, 0), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(5, 0), Position::new(4, 0), None))?;
+
+        assert!(!destinations(&board, &Position::new(4, 4))?.contains(&Position::new(5, 3)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_en_passant_without_a_pushed_pawn() -> Result<(), String> {
+        // parse_fen refuses such a square, but boards can be set up by hand
+        let mut board = fen::parse_fen("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1")?;
+        board.set_en_passant(Position::from_algebraic("e6"));
+        assert!(destinations(&board, &Position::new(4, 3))?.contains(&Position::new(5, 4)));
+        assert_eq!(ChessEngine::perft(&board, 1)?, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_promotion() -> Result<(), String> {
+        let mut board = Board::new(8)?;
+        let mut map = HashMap::new();
+        map.insert(Position::new(0, 4), Piece::new(PieceKind::King, Color::White));
+        map.insert(Position::new(6, 0), Piece::new(PieceKind::Pawn, Color::White));
+        map.insert(Position::new(7, 1), Piece::new(PieceKind::Rook, Color::Black));
+        map.insert(Position::new(4, 4), Piece::new(PieceKind::King, Color::Black));
+        board.populate(map)?;
+        let pawn = Position::new(6, 0);
+
+        assert_eq!(ChessEngine::promotion_options(&board, &pawn, &Position::new(7, 1))?,
+                   PROMOTION_KINDS.to_vec());
+        assert!(ChessEngine::promotion_options(&board, &Position::new(0, 4), &Position::new(1, 4))?.is_empty());
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(pawn, Position::new(7, 0), None)).is_err());
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(pawn, Position::new(7, 0), Some(PieceKind::King))).is_err());
+        assert!(ChessEngine::execute_move(&mut board, &Move::new(Position::new(0, 4), Position::new(1, 4), Some(PieceKind::Queen))).is_err());
+
+        let captured = ChessEngine::execute_move(&mut board, &Move::new(pawn, Position::new(7, 1), Some(PieceKind::Knight)))?;
+        assert_eq!(captured.unwrap().kind, PieceKind::Rook);
+        assert_eq!(board.get_space(&Position::new(7, 1))?.unwrap().kind, PieceKind::Knight);
+        assert_eq!(board.get_space(&pawn)?, None);
+        Ok(())
+    }
+
+    fn setup_castling_board(extra: Vec<(Position, Piece)>) -> Result<Board, String> {
+        let mut board = Board::new(8)?;
+        let mut map = HashMap::new();
+        map.insert(Position::new(0, 4), Piece::new(PieceKind::King, Color::White));
+        map.insert(Position::new(0, 0), Piece::new(PieceKind::Rook, Color::White));
+        map.insert(Position::new(0, 7), Piece::new(PieceKind::Rook, Color::White));
+        map.insert(Position::new(7, 4), Piece::new(PieceKind::King, Color::Black));
+        map.extend(extra);
+        board.populate(map)?;
+        board.set_castling_rights(CastlingRights::all());
+        Ok(board)
+    }
+
+    #[test]
+    fn test_castling() -> Result<(), String> {
+        let king = Position::new(0, 4);
+        let mut board = setup_castling_board(vec![])?;
+        let moves = destinations(&board, &king)?;
+        assert!(moves.contains(&Position::new(0, 6)));
+        assert!(moves.contains(&Position::new(0, 2)));
+
+        ChessEngine::execute_move(&mut board, &Move::new(king, Position::new(0, 2), None))?;
+        assert_eq!(board.get_space(&Position::new(0, 3))?.unwrap().kind, PieceKind::Rook);
+        assert_eq!(board.get_space(&Position::new(0, 0))?, None);
+        assert!(!board.get_castling_rights().white_king_side);
+
+        let mut board = setup_castling_board(vec![])?;
+        ChessEngine::execute_move(&mut board, &Move::new(king, Position::new(0, 6), None))?;
+        assert_eq!(board.get_space(&Position::new(0, 5))?.unwrap().kind, PieceKind::Rook);
+        assert_eq!(board.get_space(&Position::new(0, 7))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_castling_through_check() -> Result<(), String> {
+        let king = Position::new(0, 4);
+        let rook = |col| (Position::new(5, col), Piece::new(PieceKind::Rook, Color::Black));
+        // out of check
+        let board = setup_castling_board(vec![rook(4)])?;
+        let moves = destinations(&board, &king)?;
+        assert!(!moves.contains(&Position::new(0, 6)) && !moves.contains(&Position::new(0, 2)));
+        // through an attacked square
+        let board = setup_castling_board(vec![rook(5), rook(3)])?;
+        let moves = destinations(&board, &king)?;
+        assert!(!moves.contains(&Position::new(0, 6)) && !moves.contains(&Position::new(0, 2)));
+        // into check
+        let board = setup_castling_board(vec![rook(6), rook(2)])?;
+        let moves = destinations(&board, &king)?;
+        assert!(!moves.contains(&Position::new(0, 6)) && !moves.contains(&Position::new(0, 2)));
+        // only the rook passes over an attacked square on the queen side
+        let board = setup_castling_board(vec![rook(1)])?;
+        assert!(destinations(&board, &king)?.contains(&Position::new(0, 2)));
+        // blocked
+        let board = setup_castling_board(vec![(Position::new(0, 1), Piece::new(PieceKind::Knight, Color::White))])?;
+        assert!(!destinations(&board, &king)?.contains(&Position::new(0, 2)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_castling_rights_lost() -> Result<(), String> {
+        let king = Position::new(0, 4);
+        let mut board = setup_castling_board(vec![
+            (Position::new(6, 7), Piece::new(PieceKind::Rook, Color::Black)),
+            (Position::new(0, 5), Piece::new(PieceKind::Bishop, Color::White)),
+        ])?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(0, 0), Position::new(1, 0), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(6, 7), Position::new(0, 7), None))?;
+        let rights = board.get_castling_rights();
+        assert!(!rights.white_queen_side && !rights.white_king_side);
+
+        // moving back does not restore the right
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 0), Position::new(0, 0), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(7, 4), Position::new(7, 3), None))?;
+        assert!(!destinations(&board, &king)?.contains(&Position::new(0, 2)));
+        assert!(!board.get_castling_rights().black_king_side);
+        Ok(())
+    }
+
+    fn search(fen: &str, depth: u32) -> Result<Vec<ScoredMove>, String> {
+        let board = ChessEngine::create_board(BoardSetup::Fen(fen.to_string()))?;
+        Ok(ChessEngine::best_moves(&board, &SearchLimits::depth(depth))?.moves)
+    }
+
+    // (name, FEN, leaf nodes at depth 1, 2, 3, ...) from the Chess Programming Wiki
+    const PERFT_POSITIONS: [(&str, &str, &[u64]); 6] = [
+        ("start", fen::STARTING_FEN, &[20, 400, 8_902, 197_281]),
+        ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2_039, 97_862]),
+        ("en passant", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2_812, 43_238]),
+        ("promotion", "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9_467]),
+        ("discovered check", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1_486, 62_379]),
+        ("middlegame", "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2_079, 89_890]),
+    ];
+
+    #[test]
+    fn test_perft() -> Result<(), String> {
+        for (name, fen, counts) in PERFT_POSITIONS.iter() {
+            let board = fen::parse_fen(fen)?;
+            assert_eq!(ChessEngine::perft(&board, 0)?, 1);
+            for (depth, count) in counts.iter().enumerate() {
+                assert_eq!(ChessEngine::perft(&board, depth as u32 + 1)?, *count, "{} at depth {}", name, depth + 1);
+            }
+        }
+        Ok(())
+    }
+
+    // deeper counts, which take too long for every run: cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn test_perft_deep() -> Result<(), String> {
+        let positions = [
+            (fen::STARTING_FEN, 5, 4_865_609),
+            (PERFT_POSITIONS[1].1, 4, 4_085_603),
+            (PERFT_POSITIONS[2].1, 5, 674_624),
+            (PERFT_POSITIONS[3].1, 4, 422_333),
+            (PERFT_POSITIONS[4].1, 4, 2_103_487),
+        ];
+        for (fen, depth, count) in positions.iter() {
+            assert_eq!(ChessEngine::perft(&fen::parse_fen(fen)?, *depth)?, *count, "{} at depth {}", fen, depth);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_backends_agree() -> Result<(), String> {
+        for (name, fen, counts) in PERFT_POSITIONS.iter() {
+            let board = fen::parse_fen(fen)?;
+            assert_eq!(ChessEngine::perft_with(&board, 2, Backend::Mailbox)?, counts[1], "{}", name);
+            for (pos, _) in board.get_piece_positions().iter() {
+                let mut bitboard = ChessEngine::possible_moves_with(&board, pos, Backend::Bitboard)?;
+                let mut mailbox = ChessEngine::possible_moves_with(&board, pos, Backend::Mailbox)?;
+                bitboard.sort_by_key(|mv| (mv.to.row, mv.to.col, mv.promotion.map(|kind| kind.to_char())));
+                mailbox.sort_by_key(|mv| (mv.to.row, mv.to.col, mv.promotion.map(|kind| kind.to_char())));
+                assert_eq!(bitboard, mailbox, "{} from {}", name, pos.to_algebraic());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_make_unmake() -> Result<(), String> {
+        for (name, fen, _) in PERFT_POSITIONS.iter() {
+            let mut board = fen::parse_fen(fen)?;
+            let before = board.clone();
+            for mv in ChessEngine::all_legal_moves(&before)? {
+                let undo = ChessEngine::make_move(&mut board, &mv)?;
+                assert_eq!(undo.captured.is_some(), mv.is_capture());
+                ChessEngine::unmake_move(&mut board, &undo)?;
+                assert_eq!(fen::to_fen(&board), fen::to_fen(&before), "{} after {:?}", name, mv);
+                assert_eq!(board.get_piece_positions(), before.get_piece_positions());
+                assert_eq!(board.get_bitboards(), before.get_bitboards());
+                assert_eq!(board.get_hash(), before.get_hash());
+            }
+        }
+        // an illegal move leaves the board alone
+        let mut board = fen::parse_fen(fen::STARTING_FEN)?;
+        assert!(ChessEngine::make_move(&mut board, &Move::new(Position::new(1, 4), Position::new(4, 4), None)).is_err());
+        assert_eq!(fen::to_fen(&board), fen::STARTING_FEN);
+        Ok(())
+    }
+
+    #[test]
+    fn test_divide() -> Result<(), String> {
+        let board = fen::parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let counts = ChessEngine::divide(&board, 2)?;
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|(_, count)| count).sum::<u64>(), 2_039);
+        // castling either way leaves Black 43 replies
+        let castle = |to| counts.iter().find(|(mv, _)| mv.from == Position::new(0, 4) && mv.to == to).unwrap().1;
+        assert_eq!(castle(Position::new(0, 6)), 43);
+        assert_eq!(castle(Position::new(0, 2)), 43);
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_moves_finds_mate() -> Result<(), String> {
+        let results = search("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", 2)?;
+        assert_eq!(results[0].mv, Move::new(Position::new(0, 0), Position::new(7, 0), None));
+        assert_eq!(ChessEngine::mate_in(results[0].score), Some(1));
+        assert_eq!(results[0].pv.len(), 1);
+        assert!(results.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        // the mated side sees it coming
+        let results = search("6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1", 3)?;
+        assert!(results.iter().all(|result| result.mv.to != Position::new(5, 7) || result.score < 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_moves_wins_material() -> Result<(), String> {
+        // the queen on d5 is hanging, and is the only piece that can take the rook
+        let results = search("4k3/8/8/1n1q4/8/8/3R4/4K3 w - - 0 1", 2)?;
+        assert!(results[0].mv.matches(&Move::new(Position::new(1, 3), Position::new(4, 3), None)));
+        assert_eq!(results[0].mv.captured, Some(PieceKind::Queen));
+        assert!(results[0].score > results[1].score + 500);
+        assert_eq!(results[0].pv.len(), 2);
+        assert_eq!(search("4k3/8/8/8/8/8/8/4K2r w - - 0 1", 0)?.len(), 3);
+        assert!(search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 2)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterative_deepening() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Basic)?;
+        let result = ChessEngine::best_moves(&board, &SearchLimits::depth(3))?;
+        assert_eq!(result.stats.depth, 3);
+        assert!(result.stats.nodes > 400);
+        assert_eq!(result.moves.len(), 20);
+
+        // no need to look further once a mate is found
+        let board = ChessEngine::create_board(BoardSetup::Fen(String::from("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1")))?;
+        let result = ChessEngine::best_moves(&board, &SearchLimits::depth(6))?;
+        assert_eq!(result.stats.depth, 2);
+        assert_eq!(result.moves[0].mv, Move::new(Position::new(0, 0), Position::new(7, 0), None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transposition_table_reuse() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Fen(
+            String::from("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3")))?;
+        let mut table = TranspositionTable::new(1);
+        let first = ChessEngine::best_moves_with_table(&board, &SearchLimits::depth(3), &mut table)?;
+        let second = ChessEngine::best_moves_with_table(&board, &SearchLimits::depth(3), &mut table)?;
+        assert!(second.stats.nodes < first.stats.nodes / 2);
+        assert_eq!(second.moves[0].score, first.moves[0].score);
+
+        let fresh = ChessEngine::best_moves(&board, &SearchLimits::depth(3))?;
+        assert_eq!(fresh.moves[0].score, first.moves[0].score);
+        Ok(())
+    }
+
+    fn exchange(fen: &str, from: &str, to: &str) -> Result<i32, String> {
+        let board = fen::parse_fen(fen)?;
+        let mv = Move::new(Position::from_algebraic(from).unwrap(), Position::from_algebraic(to).unwrap(), None);
+        let mv = ChessEngine::all_legal_moves(&board)?.into_iter().find(|legal| legal.matches(&mv)).unwrap();
+        ChessEngine::static_exchange(&board, &mv)
+    }
+
+    #[test]
+    fn test_static_exchange() -> Result<(), String> {
+        assert_eq!(exchange("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4", "d5")?, 100);
+        assert_eq!(exchange("4k3/8/2p5/3p4/4P3/8/8/4K3 w - - 0 1", "e4", "d5")?, 0);
+        assert_eq!(exchange("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1", "d5")?, -800);
+        // the second rook joins in once the first has gone
+        assert_eq!(exchange("4k3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2", "d5")?, 100);
+        assert_eq!(exchange("3rk3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2", "d5")?, -400);
+        assert_eq!(exchange("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1", "d5")?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quiescence_sees_recapture() -> Result<(), String> {
+        let board = fen::parse_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1")?;
+        let queen_takes = Move::new(Position::new(0, 3), Position::new(4, 3), None);
+        let limits = SearchLimits { features: SearchFeatures::none(), ..SearchLimits::depth(1) };
+        assert!(ChessEngine::best_moves(&board, &limits)?.moves[0].mv.matches(&queen_takes));
+        assert!(!ChessEngine::best_moves(&board, &SearchLimits::depth(1))?.moves[0].mv.matches(&queen_takes));
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_ordering_saves_nodes() -> Result<(), String> {
+        let board = fen::parse_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3")?;
+        let nodes = |features: SearchFeatures| -> Result<u64, String> {
+            let limits = SearchLimits { features, ..SearchLimits::depth(3) };
+            Ok(ChessEngine::best_moves(&board, &limits)?.stats.nodes)
+        };
+        let unordered = nodes(SearchFeatures::none())?;
+        let captures = nodes(SearchFeatures { capture_ordering: true, ..SearchFeatures::none() })?;
+        let all = nodes(SearchFeatures { quiescence: false, ..SearchFeatures::default() })?;
+        assert!(captures < unordered);
+        assert!(all < captures);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_limits() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Basic)?;
+        // the first iteration is always finished
+        let result = ChessEngine::best_moves(&board, &SearchLimits::time(Duration::from_millis(0)))?;
+        assert_eq!(result.stats.depth, 1);
+        assert_eq!(result.moves.len(), 20);
+
+        let stop = Arc::new(AtomicBool::new(true));
+        let limits = SearchLimits { stop: Some(stop), ..SearchLimits::depth(5) };
+        assert_eq!(ChessEngine::best_moves(&board, &limits)?.stats.depth, 1);
+
+        let result = ChessEngine::best_moves(&board, &SearchLimits::time(Duration::from_millis(500)))?;
+        assert!(result.stats.depth >= 2);
+        assert!(result.stats.time_ms < 1500);
         Ok(())
     }
 
@@ -3,5 +3,8 @@
 pub enum GameError {
     DoesNotExist,
     NotAllowed,
+    /// The game has moved on from the position the request was made for.
+    Conflict,
+    InvalidInput(String),
     Internal(String),
 }
@@ -0,0 +1,466 @@
+use super::board::{Board, Color, PieceKind, Position};
+use super::engine::ChessEngine;
+use serde::{Serialize, Deserialize};
+use std::fmt::Debug;
+use std::fs;
+
+/// Scores positions for the search.
+pub trait Evaluator: Debug + Send + Sync {
+    /// The score of `board` in centipawns for the side to move.
+    fn evaluate(&self, board: &Board) -> i32;
+}
+
+/// A weight with one value for the middlegame and another for the endgame, blended by how
+/// much material is left.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Phased {
+    pub middlegame: i32,
+    pub endgame: i32,
+}
+
+impl Phased {
+    pub const fn new(middlegame: i32, endgame: i32) -> Phased {
+        Phased { middlegame, endgame }
+    }
+}
+
+/// A bonus for each square from White's side, listed from the eighth rank down to the first
+/// so that it reads like a diagram. Black reads it upside down.
+pub type PieceSquareTable = [[i32; 8]; 8];
+
+/// A piece-square table for the middlegame and another for the endgame, blended like `Phased`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhasedTable {
+    pub middlegame: PieceSquareTable,
+    pub endgame: PieceSquareTable,
+}
+
+impl PhasedTable {
+    pub const fn new(middlegame: PieceSquareTable, endgame: PieceSquareTable) -> PhasedTable {
+        PhasedTable { middlegame, endgame }
+    }
+}
+
+/// The terms of `TaperedEvaluator`. Any left out of a weights file keep their default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Weights {
+    pub pawn: Phased,
+    pub knight: Phased,
+    pub bishop: Phased,
+    pub rook: Phased,
+    pub queen: Phased,
+    /// For each pawn on a file beyond the first.
+    pub doubled_pawn: Phased,
+    /// For each pawn with no friendly pawns on the files beside it.
+    pub isolated_pawn: Phased,
+    /// For each rank a pawn with no enemy pawns ahead of it has advanced.
+    pub passed_pawn: Phased,
+    /// For each friendly pawn directly in front of the king or beside that square.
+    pub king_shield: Phased,
+    /// For each enemy piece that can move next to the king.
+    pub king_attacker: Phased,
+    /// For each square a knight, bishop, rook or queen can move to.
+    pub mobility: Phased,
+    pub pawn_table: PhasedTable,
+    pub knight_table: PhasedTable,
+    pub bishop_table: PhasedTable,
+    pub rook_table: PhasedTable,
+    pub queen_table: PhasedTable,
+    pub king_table: PhasedTable,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            pawn: Phased::new(100, 120),
+            knight: Phased::new(320, 300),
+            bishop: Phased::new(330, 320),
+            rook: Phased::new(500, 520),
+            queen: Phased::new(900, 950),
+            doubled_pawn: Phased::new(-10, -20),
+            isolated_pawn: Phased::new(-10, -15),
+            passed_pawn: Phased::new(5, 20),
+            king_shield: Phased::new(10, 0),
+            king_attacker: Phased::new(-15, -5),
+            mobility: Phased::new(2, 3),
+            pawn_table: PhasedTable::new(PAWN_MIDDLEGAME_TABLE, PAWN_ENDGAME_TABLE),
+            knight_table: PhasedTable::new(KNIGHT_TABLE, KNIGHT_TABLE),
+            bishop_table: PhasedTable::new(BISHOP_TABLE, BISHOP_TABLE),
+            rook_table: PhasedTable::new(ROOK_TABLE, ROOK_TABLE),
+            queen_table: PhasedTable::new(QUEEN_TABLE, QUEEN_TABLE),
+            king_table: PhasedTable::new(KING_MIDDLEGAME_TABLE, KING_ENDGAME_TABLE),
+        }
+    }
+}
+
+impl Weights {
+    pub fn from_json(json: &str) -> Result<Weights, String> {
+        serde_json::from_str(json).map_err(|err| format!("Invalid evaluation weights: {}", err))
+    }
+
+    pub fn from_file(path: &str) -> Result<Weights, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read evaluation weights from {}: {}", path, err))?;
+        Weights::from_json(&json)
+    }
+
+    fn material(&self, kind: PieceKind) -> Phased {
+        match kind {
+            PieceKind::Pawn => self.pawn,
+            PieceKind::Knight => self.knight,
+            PieceKind::Bishop => self.bishop,
+            PieceKind::Rook => self.rook,
+            PieceKind::Queen => self.queen,
+            PieceKind::King => Phased::new(0, 0),
+        }
+    }
+
+    fn piece_square(&self, kind: PieceKind, color: Color, pos: &Position) -> Phased {
+        let table = match kind {
+            PieceKind::Pawn => &self.pawn_table,
+            PieceKind::Knight => &self.knight_table,
+            PieceKind::Bishop => &self.bishop_table,
+            PieceKind::Rook => &self.rook_table,
+            PieceKind::Queen => &self.queen_table,
+            PieceKind::King => &self.king_table,
+        };
+        let rank = 7 - relative_rank(pos.row, color) as usize;
+        let col = pos.col as usize;
+        Phased::new(table.middlegame[rank][col], table.endgame[rank][col])
+    }
+}
+
+// the game phase runs from this with all the pieces on the board down to 0 with none left
+const MAX_PHASE: i32 = 24;
+
+/// Material, piece-square tables, pawn structure, king safety and mobility, each scored
+/// separately for the middlegame and the endgame and blended by the game phase.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaperedEvaluator {
+    pub weights: Weights,
+}
+
+impl TaperedEvaluator {
+    pub fn new(weights: Weights) -> TaperedEvaluator {
+        TaperedEvaluator { weights }
+    }
+}
+
+impl Evaluator for TaperedEvaluator {
+    fn evaluate(&self, board: &Board) -> i32 {
+        let mut middlegame = 0;
+        let mut endgame = 0;
+        let mut phase = 0;
+        let mut add = |color: Color, term: Phased, times: i32| {
+            let sign = if color == board.get_turn() { 1 } else { -1 };
+            middlegame += sign * term.middlegame * times;
+            endgame += sign * term.endgame * times;
+        };
+        let weights = &self.weights;
+        let pawns = PawnFiles::new(board);
+        let kings = [Color::White, Color::Black].iter()
+            .map(|color| find_king(board, *color))
+            .collect::<Vec<_>>();
+
+        for (pos, piece) in board.get_piece_positions().iter() {
+            add(piece.color, weights.material(piece.kind), 1);
+            add(piece.color, weights.piece_square(piece.kind, piece.color, pos), 1);
+            phase += match piece.kind {
+                PieceKind::Knight | PieceKind::Bishop => 1,
+                PieceKind::Rook => 2,
+                PieceKind::Queen => 4,
+                _ => 0,
+            };
+            match piece.kind {
+                PieceKind::Pawn => {
+                    let advanced = if piece.color == Color::White { pos.row - 1 } else { 6 - pos.row };
+                    if pawns.is_passed(pos, piece.color) {
+                        add(piece.color, weights.passed_pawn, advanced);
+                    }
+                    if pawns.is_isolated(pos.col, piece.color) {
+                        add(piece.color, weights.isolated_pawn, 1);
+                    }
+                }
+                PieceKind::King => {
+                    add(piece.color, weights.king_shield, king_shield(board, pos, piece.color));
+                }
+                _ => {
+                    let moves = ChessEngine::possible_moves(board, pos).unwrap_or_default();
+                    add(piece.color, weights.mobility, moves.len() as i32);
+                    let enemy_king = kings[if piece.color == Color::White { 1 } else { 0 }];
+                    let attacks_king = enemy_king.is_some_and(|king| moves.iter()
+                        .any(|mv| (mv.to.row - king.row).abs() <= 1 && (mv.to.col - king.col).abs() <= 1));
+                    if attacks_king {
+                        add(piece.color.opposite(), weights.king_attacker, 1);
+                    }
+                }
+            }
+        }
+        for color in [Color::White, Color::Black].iter() {
+            add(*color, weights.doubled_pawn, pawns.doubled(*color));
+        }
+
+        let phase = phase.min(MAX_PHASE);
+        (middlegame * phase + endgame * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+// how many pawns of each color stand on each file, and how far up the board they reach
+struct PawnFiles {
+    counts: [[i32; 8]; 2],
+    // the rank of the pawn furthest back on each file, from each side's point of view
+    rearmost: [[Option<i32>; 8]; 2],
+}
+
+impl PawnFiles {
+    fn new(board: &Board) -> PawnFiles {
+        let mut files = PawnFiles { counts: [[0; 8]; 2], rearmost: [[None; 8]; 2] };
+        for (pos, piece) in board.get_piece_positions().iter() {
+            if piece.kind != PieceKind::Pawn {
+                continue;
+            }
+            let side = PawnFiles::side(piece.color);
+            let col = pos.col as usize;
+            files.counts[side][col] += 1;
+            let rank = relative_rank(pos.row, piece.color);
+            files.rearmost[side][col] = Some(files.rearmost[side][col].map_or(rank, |r| r.min(rank)));
+        }
+        files
+    }
+
+    fn side(color: Color) -> usize {
+        if color == Color::White { 0 } else { 1 }
+    }
+
+    fn doubled(&self, color: Color) -> i32 {
+        self.counts[PawnFiles::side(color)].iter().map(|count| (count - 1).max(0)).sum()
+    }
+
+    fn is_isolated(&self, col: i32, color: Color) -> bool {
+        let counts = &self.counts[PawnFiles::side(color)];
+        [col - 1, col + 1].iter().all(|c| !(0..8).contains(c) || counts[*c as usize] == 0)
+    }
+
+    // no enemy pawn ahead of it on its own file or the ones beside it
+    fn is_passed(&self, pos: &Position, color: Color) -> bool {
+        let enemy = &self.rearmost[PawnFiles::side(color.opposite())];
+        // an enemy pawn's rank seen from our side
+        let rank = relative_rank(pos.row, color);
+        (pos.col - 1..=pos.col + 1)
+            .filter(|c| (0..8).contains(c))
+            .all(|c| enemy[c as usize].is_none_or(|enemy_rank| 7 - enemy_rank <= rank))
+    }
+}
+
+// ranks counted from `color`'s own side of the board
+fn relative_rank(row: i32, color: Color) -> i32 {
+    if color == Color::White { row } else { 7 - row }
+}
+
+fn find_king(board: &Board, color: Color) -> Option<Position> {
+    board.get_piece_positions().iter()
+        .find(|(_, piece)| piece.kind == PieceKind::King && piece.color == color)
+        .map(|(pos, _)| *pos)
+}
+
+// friendly pawns on the three squares in front of the king
+fn king_shield(board: &Board, king: &Position, color: Color) -> i32 {
+    let forward = if color == Color::White { 1 } else { -1 };
+    (king.col - 1..=king.col + 1)
+        .filter(|col| matches!(
+            board.get_space(&Position::new(king.row + forward, *col)),
+            Ok(Some(piece)) if piece.kind == PieceKind::Pawn && piece.color == color
+        ))
+        .count() as i32
+}
+
+// the default piece-square tables, of which only the pawns' and the king's differ in the
+// endgame
+
+const PAWN_MIDDLEGAME_TABLE: PieceSquareTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [10, 10, 20, 30, 30, 20, 10, 10],
+    [5, 5, 10, 25, 25, 10, 5, 5],
+    [0, 0, 0, 20, 20, 0, 0, 0],
+    [5, -5, -10, 0, 0, -10, -5, 5],
+    [5, 10, 10, -20, -20, 10, 10, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+const KNIGHT_TABLE: PieceSquareTable = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20, 0, 0, 0, 0, -20, -40],
+    [-30, 0, 10, 15, 15, 10, 0, -30],
+    [-30, 5, 15, 20, 20, 15, 5, -30],
+    [-30, 0, 15, 20, 20, 15, 0, -30],
+    [-30, 5, 10, 15, 15, 10, 5, -30],
+    [-40, -20, 0, 5, 5, 0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+const BISHOP_TABLE: PieceSquareTable = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 5, 5, 10, 10, 5, 5, -10],
+    [-10, 0, 10, 10, 10, 10, 0, -10],
+    [-10, 10, 10, 10, 10, 10, 10, -10],
+    [-10, 5, 0, 0, 0, 0, 5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+const ROOK_TABLE: PieceSquareTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 10, 10, 10, 10, 10, 10, 5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [0, 0, 0, 5, 5, 0, 0, 0],
+];
+
+const QUEEN_TABLE: PieceSquareTable = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [0, 0, 5, 5, 5, 5, 0, -5],
+    [-10, 5, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 0, 0, 0, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+const KING_MIDDLEGAME_TABLE: PieceSquareTable = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [20, 20, 0, 0, 0, 0, 20, 20],
+    [20, 30, 10, 0, 0, 10, 30, 20],
+];
+
+const PAWN_ENDGAME_TABLE: PieceSquareTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [80, 80, 80, 80, 80, 80, 80, 80],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [30, 30, 30, 30, 30, 30, 30, 30],
+    [20, 20, 20, 20, 20, 20, 20, 20],
+    [10, 10, 10, 10, 10, 10, 10, 10],
+    [10, 10, 10, 10, 10, 10, 10, 10],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+// with the queens off the king comes out to fight
+const KING_ENDGAME_TABLE: PieceSquareTable = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10, 0, 0, -10, -20, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -30, 0, 0, 0, 0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fen;
+
+    fn evaluate(fen: &str) -> i32 {
+        TaperedEvaluator::default().evaluate(&fen::parse_fen(fen).unwrap())
+    }
+
+    // the same position with the colors swapped and the board turned around
+    fn mirror(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let placement: Vec<String> = fields[0].split('/').rev()
+            .map(|rank| rank.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect())
+            .collect();
+        let turn = if fields[1] == "w" { "b" } else { "w" };
+        format!("{} {} - - 0 1", placement.join("/"), turn)
+    }
+
+    #[test]
+    fn test_symmetry() {
+        assert_eq!(evaluate(fen::STARTING_FEN), 0);
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/pp4pp/8/3P4/8/8/PP3PPP/4K3 b - - 0 1",
+        ];
+        for fen in fens.iter() {
+            assert_eq!(evaluate(fen), evaluate(&mirror(fen)), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_material_and_side_to_move() {
+        assert!(evaluate("4k3/8/8/8/8/8/8/R3K3 w - - 0 1") > 400);
+        assert!(evaluate("4k3/8/8/8/8/8/8/R3K3 b - - 0 1") < -400);
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        let board = fen::parse_fen("4k3/8/8/8/8/8/2P1P1PP/4K3 w - - 0 1").unwrap();
+        let pawns = PawnFiles::new(&board);
+        assert!(pawns.is_isolated(2, Color::White));
+        assert!(!pawns.is_isolated(6, Color::White));
+        assert_eq!(pawns.doubled(Color::White), 0);
+
+        // a passed pawn is worth more than one facing an enemy pawn
+        assert!(evaluate("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1") > evaluate("4k3/2p5/8/3P4/8/8/8/4K3 w - - 0 1") + 100);
+        // doubled pawns are worth less than the same pawns side by side
+        assert!(evaluate("4k3/8/8/8/8/3P4/3P4/4K3 w - - 0 1") < evaluate("4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn test_king_placement_depends_on_phase() {
+        // castled behind pawns in the middlegame, but in the center once the pieces are off
+        assert!(evaluate("rnbq1rk1/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1RK1 w - - 0 1")
+                > evaluate("rnbq1rk1/pppppppp/8/8/8/4K3/PPPPPPPP/RNBQ1R2 w - - 0 1"));
+        assert!(evaluate("6k1/8/8/8/4K3/8/8/8 w - - 0 1") > evaluate("6k1/8/8/8/8/8/8/7K w - - 0 1"));
+    }
+
+    #[test]
+    fn test_weights_from_json() {
+        let weights = Weights::from_json(r#"{"pawn": {"middlegame": 200, "endgame": 250}}"#).unwrap();
+        assert_eq!(weights.pawn, Phased::new(200, 250));
+        assert_eq!(weights.queen, Weights::default().queen);
+        assert!(Weights::from_json(r#"{"pawns": {"middlegame": 200, "endgame": 250}}"#).is_err());
+        assert!(Weights::from_json("not json").is_err());
+
+        let path = std::env::temp_dir().join(format!("chess-weights-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string(&weights).unwrap()).unwrap();
+        assert_eq!(Weights::from_file(path.to_str().unwrap()).unwrap(), weights);
+        fs::remove_file(&path).unwrap();
+        assert!(Weights::from_file("/no/such/weights.json").is_err());
+
+        let board = fen::parse_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        assert!(TaperedEvaluator::new(weights).evaluate(&board) > TaperedEvaluator::default().evaluate(&board));
+    }
+
+    #[test]
+    fn test_piece_square_tables_from_json() {
+        // a knight worth a pawn more on a1 than anywhere else
+        let mut table = [[0; 8]; 8];
+        table[7][0] = 100;
+        let json = serde_json::json!({"knight_table": {"middlegame": table, "endgame": table}}).to_string();
+        let weights = Weights::from_json(&json).unwrap();
+        assert_eq!(weights.knight_table, PhasedTable::new(table, table));
+        assert_eq!(weights.pawn_table, Weights::default().pawn_table);
+        assert!(Weights::from_json(r#"{"knight_table": {"middlegame": [[0]], "endgame": [[0]]}}"#).is_err());
+
+        let corner = fen::parse_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1").unwrap();
+        let center = fen::parse_fen("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1").unwrap();
+        let evaluator = TaperedEvaluator::new(weights);
+        assert!(evaluator.evaluate(&corner) > evaluator.evaluate(&center));
+        assert!(TaperedEvaluator::default().evaluate(&corner) < TaperedEvaluator::default().evaluate(&center));
+    }
+}
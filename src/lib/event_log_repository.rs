@@ -0,0 +1,541 @@
+use super::board::Board;
+use super::engine::{ChessEngine, GameResult, Undo};
+use super::errors::GameError;
+use super::fen;
+use super::game_repository::{self, Game, GameRepository, MoveRecord, PlayMove};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// How many plies apart a game's snapshots are.
+pub const SNAPSHOT_INTERVAL: usize = 20;
+
+// one line of a game's log. Boards are written as FEN, which leaves out which pieces have
+// moved, something nothing relies on.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Created { date: String, fen: String },
+    Moved {
+        record: MoveRecord,
+        /// The result, when the move ended the game.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<GameResult>,
+    },
+    /// The board after the first `ply` moves, so that reading the game back need not replay
+    /// them. A compacted log has one for every snapshot taken, each carrying the moves since the
+    /// one before it in place of their own events.
+    Snapshot {
+        ply: usize,
+        fen: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        moves: Vec<MoveRecord>,
+    },
+    Tagged { tags: Vec<(String, String)> },
+    Finished { result: GameResult },
+}
+
+// what a game's log says, along with the board after its last move
+struct GameLog {
+    path: PathBuf,
+    date: String,
+    tags: Vec<(String, String)>,
+    result: Option<GameResult>,
+    initial: Board,
+    moves: Vec<MoveRecord>,
+    snapshots: Vec<(usize, Board)>,
+    board: Board,
+    // what the moves since the last snapshot changed
+    history: Vec<Undo>,
+}
+
+impl GameLog {
+
+    fn new(path: PathBuf, date: String, initial: Board) -> GameLog {
+        GameLog {
+            path,
+            date,
+            tags: vec![],
+            result: None,
+            board: initial.clone(),
+            initial,
+            moves: vec![],
+            snapshots: vec![],
+            history: vec![],
+        }
+    }
+
+    // writes `event` at the end of the log and waits for it to reach the disk. A write that
+    // fails part way is cut off again, so that later events are not written after it.
+    fn append(&self, event: &Event) -> Result<(), GameError> {
+        let mut line = serde_json::to_string(event).map_err(|err| GameError::Internal(err.to_string()))?;
+        line.push('\n');
+        let mut file = OpenOptions::new().append(true).open(&self.path).map_err(io_error)?;
+        let length = file.metadata().map_err(io_error)?.len();
+        if let Err(err) = file.write_all(line.as_bytes()).and_then(|_| file.sync_data()) {
+            let _ = file.set_len(length);
+            return Err(io_error(err));
+        }
+        Ok(())
+    }
+
+    // rewrites the log as its snapshots followed by one of the game so far, in place of all
+    // of its moves and events, and swaps it in for the old log in one rename, so that a crash
+    // leaves one or the other
+    fn compact(&self) -> Result<(), GameError> {
+        let mut events = vec![Event::Created { date: self.date.clone(), fen: fen::to_fen(&self.initial) }];
+        let mut snapshots: Vec<(usize, &Board)> = self.snapshots.iter().map(|(at, board)| (*at, board)).collect();
+        if snapshots.last().map(|(at, _)| *at) != Some(self.moves.len()) {
+            snapshots.push((self.moves.len(), &self.board));
+        }
+        let mut start = 0;
+        for (at, board) in snapshots {
+            events.push(Event::Snapshot { ply: at, fen: fen::to_fen(board), moves: self.moves[start..at].to_vec() });
+            start = at;
+        }
+        if !self.tags.is_empty() {
+            events.push(Event::Tagged { tags: self.tags.clone() });
+        }
+        if let Some(result) = self.result {
+            events.push(Event::Finished { result });
+        }
+        let mut text = String::new();
+        for event in events.iter() {
+            text.push_str(&serde_json::to_string(event).map_err(|err| GameError::Internal(err.to_string()))?);
+            text.push('\n');
+        }
+        let temporary = self.path.with_extension("log.tmp");
+        let mut file = File::create(&temporary).map_err(io_error)?;
+        file.write_all(text.as_bytes()).and_then(|_| file.sync_all()).map_err(io_error)?;
+        fs::rename(&temporary, &self.path).map_err(io_error)?;
+        sync_dir(self.path.parent().unwrap_or_else(|| Path::new(".")))
+    }
+
+    // the board after the first `ply` moves, replayed from the last snapshot before it
+    fn board_at(&self, ply: usize) -> Result<Board, GameError> {
+        if ply > self.moves.len() {
+            return Err(GameError::InvalidInput(format!("The game has only {} moves", self.moves.len())));
+        }
+        let (start, snapshot) = self.snapshots.iter().rev()
+            .find(|(at, _)| *at <= ply)
+            .map_or((0, &self.initial), |(at, board)| (*at, board));
+        let mut board = snapshot.clone();
+        for record in self.moves[start..ply].iter() {
+            ChessEngine::play_unchecked(&mut board, &record.mv).map_err(GameError::Internal)?;
+        }
+        Ok(board)
+    }
+
+    fn to_game(&self, id: u32) -> Result<Game, GameError> {
+        let mut game = Game::resume(id, self.date.clone(), self.initial.clone(), self.moves.clone(),
+                                    self.board.clone(), self.history.clone());
+        game.set_tags(self.tags.clone());
+        game.set_result(self.result);
+        Ok(game)
+    }
+
+    // reads the log at `path` back. A crash in the middle of writing an event leaves a last
+    // line without its newline, which is cut off, and `None` means the crash came before the
+    // game was created, and the log is removed. Anything else wrong with the log is
+    // corruption: it is moved aside for someone to look at, rather than read or thrown away,
+    // and `None` is returned as well.
+    fn recover(path: &Path) -> Result<Option<GameLog>, String> {
+        let bytes = fs::read(path).map_err(|err| err.to_string())?;
+        let end = bytes.iter().rposition(|byte| *byte == b'\n').map_or(0, |newline| newline + 1);
+        if end == 0 {
+            fs::remove_file(path).map_err(|err| err.to_string())?;
+            return Ok(None);
+        }
+        let log = match GameLog::replay(path, &bytes[..end - 1]) {
+            Ok(log) => log,
+            Err(reason) => {
+                let aside = path.with_extension("log.corrupt");
+                fs::rename(path, &aside).map_err(|err| err.to_string())?;
+                eprintln!("{} is corrupt ({}), and was moved to {}", path.display(), reason, aside.display());
+                return Ok(None);
+            }
+        };
+        if end < bytes.len() {
+            eprintln!("Recovering {}: dropping {} bytes after the last whole event", path.display(),
+                      bytes.len() - end);
+            let file = OpenOptions::new().write(true).open(path).map_err(|err| err.to_string())?;
+            file.set_len(end as u64).and_then(|_| file.sync_all()).map_err(|err| err.to_string())?;
+        }
+        Ok(Some(log))
+    }
+
+    // the game the lines of `text` describe, or what is wrong with them
+    fn replay(path: &Path, text: &[u8]) -> Result<GameLog, String> {
+        let mut events = vec![];
+        for (number, line) in text.split(|byte| *byte == b'\n').enumerate() {
+            let event = serde_json::from_slice::<Event>(line).map_err(|err| format!("line {}: {}", number + 1, err))?;
+            events.push(event);
+        }
+        let mut events = events.into_iter();
+        let mut log = match events.next() {
+            Some(Event::Created { date, fen }) => GameLog::new(path.to_path_buf(), date, fen::parse_fen(&fen)?),
+            _ => return Err(String::from("the log does not start with the game being created")),
+        };
+        let events: Vec<Event> = events.collect();
+        // only the moves after the last snapshot need replaying to find the board
+        let last_snapshot = events.iter().rposition(|event| matches!(event, Event::Snapshot { .. }));
+        for (i, event) in events.into_iter().enumerate() {
+            match event {
+                Event::Created { .. } => return Err(String::from("the game is created twice")),
+                Event::Moved { record, result } => {
+                    if last_snapshot.is_none_or(|last| i > last) {
+                        let undo = ChessEngine::make_move(&mut log.board, &record.mv)
+                            .map_err(|err| format!("move {}: {}", log.moves.len() + 1, err))?;
+                        log.history.push(undo);
+                    }
+                    log.moves.push(record);
+                    log.result = log.result.or(result);
+                }
+                Event::Snapshot { ply, fen, moves } => {
+                    log.moves.extend(moves);
+                    if ply != log.moves.len() {
+                        return Err(format!("the snapshot of ply {} comes after {} moves", ply, log.moves.len()));
+                    }
+                    let board = fen::parse_fen(&fen).map_err(|err| format!("the snapshot of ply {}: {}", ply, err))?;
+                    log.board = board.clone();
+                    log.snapshots.push((ply, board));
+                }
+                Event::Tagged { tags } => log.tags = tags,
+                Event::Finished { result } => log.result = Some(result),
+            }
+        }
+        Ok(log)
+    }
+}
+
+/// Keeps each game as an append-only log of events in a file of its own, flushed to disk
+/// event by event. Every `SNAPSHOT_INTERVAL` plies a snapshot of the board is taken and the log
+/// is compacted into its snapshots and the moves between them, so that any position can be
+/// rebuilt by replaying a few moves, even once the log is read back. Logs left half written by
+/// a crash are cut back to their last whole event when the repository is opened, and corrupt
+/// ones are set aside.
+pub struct EventLogGameRepository {
+    dir: PathBuf,
+    games: RwLock<HashMap<u32, RwLock<GameLog>>>,
+}
+
+impl EventLogGameRepository {
+
+    /// Opens the logs in `dir`, creating it if need be.
+    pub fn open(dir: &str) -> Result<EventLogGameRepository, String> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).map_err(|err| format!("Could not create {}: {}", dir.display(), err))?;
+        let mut games = HashMap::new();
+        let entries = fs::read_dir(&dir).map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            let id = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("log") => path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u32>().ok()),
+                // a compaction that never got to replace its log
+                Some("tmp") => {
+                    fs::remove_file(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                    None
+                }
+                _ => None,
+            };
+            if let Some(id) = id {
+                if let Some(log) = GameLog::recover(&path).map_err(|err| format!("{}: {}", path.display(), err))? {
+                    games.insert(id, RwLock::new(log));
+                }
+            }
+        }
+        Ok(EventLogGameRepository { dir, games: RwLock::new(games) })
+    }
+
+    fn read_log<T, F>(&self, id: u32, read: F) -> Result<T, GameError>
+        where F: FnOnce(&GameLog) -> Result<T, GameError> {
+        let games = self.games.read().unwrap();
+        let log = games.get(&id).ok_or(GameError::DoesNotExist)?;
+        let log = log.read().unwrap();
+        read(&log)
+    }
+
+    fn write_log<T, F>(&self, id: u32, write: F) -> Result<T, GameError>
+        where F: FnOnce(&mut GameLog) -> Result<T, GameError> {
+        let games = self.games.read().unwrap();
+        let log = games.get(&id).ok_or(GameError::DoesNotExist)?;
+        let mut log = log.write().unwrap();
+        write(&mut log)
+    }
+}
+
+impl GameRepository for EventLogGameRepository {
+
+    fn create_game(&self, board: Board) -> Result<u32, GameError> {
+        let mut games = self.games.write().unwrap();
+        let id = game_repository::unique_game_id(|id| Ok(games.contains_key(&id)))?;
+        let game = Game::new(id, board);
+        let log = GameLog::new(self.dir.join(format!("{}.log", id)), game.get_date().to_string(),
+                               game.get_initial_board().clone());
+        OpenOptions::new().write(true).create_new(true).open(&log.path).map_err(io_error)?;
+        log.append(&Event::Created { date: log.date.clone(), fen: fen::to_fen(&log.initial) })?;
+        sync_dir(&self.dir)?;
+        games.insert(id, RwLock::new(log));
+        Ok(id)
+    }
+
+    fn get_game(&self, id: u32) -> Result<Game, GameError> {
+        self.read_log(id, |log| log.to_game(id))
+    }
+
+    fn get_latest_game_board(&self, id: u32) -> Result<Board, GameError> {
+        self.read_log(id, |log| Ok(log.board.clone()))
+    }
+
+    fn get_board_at(&self, id: u32, ply: usize) -> Result<Board, GameError> {
+        self.read_log(id, |log| log.board_at(ply))
+    }
+
+    fn get_game_result(&self, id: u32) -> Result<Option<GameResult>, GameError> {
+        self.read_log(id, |log| Ok(log.result))
+    }
+
+    fn update_game(&self, id: u32, play: PlayMove) -> Result<(), GameError> {
+        self.write_log(id, |log| {
+            // a finished game takes no more moves
+            if log.result.is_some() {
+                return Err(GameError::NotAllowed);
+            }
+            let (undo, record, result) = play(&mut log.board, log.moves.len())?;
+            // the result goes in with the move, so that a crash cannot leave one without the other
+            if let Err(err) = log.append(&Event::Moved { record: record.clone(), result }) {
+                ChessEngine::unmake_move(&mut log.board, &undo).map_err(GameError::Internal)?;
+                return Err(err);
+            }
+            log.moves.push(record);
+            log.result = result;
+            log.history.push(undo);
+            let ply = log.moves.len();
+            // the move is safely logged either way, and a compaction that fails only means more
+            // moves to replay
+            if ply % SNAPSHOT_INTERVAL == 0 && log.compact().is_ok() {
+                let board = log.board.clone();
+                log.snapshots.push((ply, board));
+                log.history.clear();
+            }
+            Ok(())
+        })
+    }
+
+    fn finish_game(&self, id: u32, result: GameResult) -> Result<(), GameError> {
+        self.write_log(id, |log| {
+            log.append(&Event::Finished { result })?;
+            log.result = Some(result);
+            Ok(())
+        })
+    }
+
+    fn set_game_tags(&self, id: u32, tags: Vec<(String, String)>) -> Result<(), GameError> {
+        self.write_log(id, |log| {
+            log.append(&Event::Tagged { tags: tags.clone() })?;
+            log.tags = tags;
+            Ok(())
+        })
+    }
+
+    fn import_game(&self, game: Game) -> Result<u32, GameError> {
+        let mut games = self.games.write().unwrap();
+        let id = game_repository::unique_game_id(|id| Ok(games.contains_key(&id)))?;
+        let mut log = GameLog::new(self.dir.join(format!("{}.log", id)), game.get_date().to_string(),
+                                   game.get_initial_board().clone());
+        log.tags = game.get_tags().clone();
+        log.result = game.get_result();
+        log.moves = game.get_moves().clone();
+        // the snapshots the game would have had, had it been played here
+        let mut board = log.initial.clone();
+        for (i, record) in log.moves.iter().enumerate() {
+            ChessEngine::play_unchecked(&mut board, &record.mv).map_err(GameError::Internal)?;
+            let ply = i + 1;
+            if ply % SNAPSHOT_INTERVAL == 0 && ply < log.moves.len() {
+                log.snapshots.push((ply, board.clone()));
+            }
+        }
+        log.board = game.get_board().clone();
+        // written whole as a compacted log, which only takes the log's place once it is all there
+        log.compact()?;
+        let snapshot = (log.moves.len(), log.board.clone());
+        log.snapshots.push(snapshot);
+        games.insert(id, RwLock::new(log));
+        Ok(id)
+    }
+}
+
+fn io_error(err: std::io::Error) -> GameError {
+    GameError::Internal(err.to_string())
+}
+
+// files created or renamed in `dir` are only there for good once the directory is on disk
+fn sync_dir(dir: &Path) -> Result<(), GameError> {
+    File::open(dir).and_then(|dir| dir.sync_all()).map_err(io_error)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::{EndReason, Move, Outcome};
+    use super::super::board::Position;
+    use super::super::game_repository::InMemoryGameRepository;
+    use std::ops::Range;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // a log directory of its own for each test, removed when it is done
+    struct TempDir(String);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+            let path = std::env::temp_dir().join(format!("chess-{}-{}-{}", name, std::process::id(), nanos));
+            TempDir(path.to_str().unwrap().to_string())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // the knights going out and back, which can go on for as long as needed
+    fn shuffle_knights(repo: &dyn GameRepository, id: u32, plies: Range<usize>) {
+        let squares = [((0, 6), (2, 5)), ((7, 6), (5, 5)), ((2, 5), (0, 6)), ((5, 5), (7, 6))];
+        for ply in plies {
+            let (from, to) = squares[ply % 4];
+            repo.update_game(id, Box::new(move |board, _| {
+                let mv = Move::new(Position::new(from.0, from.1), Position::new(to.0, to.1), None);
+                let undo = ChessEngine::make_move(board, &mv).map_err(GameError::Internal)?;
+                Ok((undo, MoveRecord { mv: undo.mv, san: String::new() }, None))
+            })).unwrap();
+        }
+    }
+
+    fn log_path(dir: &TempDir, id: u32) -> PathBuf {
+        Path::new(&dir.0).join(format!("{}.log", id))
+    }
+
+    #[test]
+    fn test_logs_survive_reopening() {
+        let dir = TempDir::new("reopen");
+        let id = {
+            let repo = EventLogGameRepository::open(&dir.0).unwrap();
+            let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+            shuffle_knights(&repo, id, 0..2 * SNAPSHOT_INTERVAL + 3);
+            repo.set_game_tags(id, vec![(String::from("Event"), String::from("Shuffle"))]).unwrap();
+            id
+        };
+        // compacted at the last snapshot, keeping the earlier one, with only the moves and tags
+        // after it on their own
+        let log = fs::read_to_string(log_path(&dir, id)).unwrap();
+        let events: Vec<&str> = log.lines().map(|line| line.split('"').nth(3).unwrap()).collect();
+        assert_eq!(events, ["created", "snapshot", "snapshot", "moved", "moved", "moved", "tagged"]);
+
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        let game = repo.get_game(id).unwrap();
+        assert_eq!(game.get_moves().len(), 2 * SNAPSHOT_INTERVAL + 3);
+        // read back from the snapshot, with only the moves after it replayed
+        assert_eq!(game.get_history().len(), 3);
+        let snapshots: Vec<usize> = repo.read_log(id, |log| Ok(log.snapshots.iter().map(|(at, _)| *at).collect())).unwrap();
+        assert_eq!(snapshots, [SNAPSHOT_INTERVAL, 2 * SNAPSHOT_INTERVAL]);
+        assert_eq!(fen::to_fen(&repo.get_latest_game_board(id).unwrap()), fen::to_fen(game.get_board()));
+        assert_eq!(fen::to_fen(game.get_board()), "rnbqkb1r/pppppppp/5n2/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 43 22");
+        assert_eq!(game.get_tags()[0].1, "Shuffle");
+        assert_eq!(repo.get_game_result(id).unwrap(), None);
+
+        // every ply can be rebuilt, from a snapshot or from the start, and agrees with the
+        // positions the in-memory repository unwinds to
+        let memory = InMemoryGameRepository::new();
+        let memory_id = memory.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        shuffle_knights(&memory, memory_id, 0..2 * SNAPSHOT_INTERVAL + 3);
+        for ply in 0..=game.get_moves().len() {
+            assert_eq!(fen::to_fen(&repo.get_board_at(id, ply).unwrap()),
+                       fen::to_fen(&memory.get_board_at(memory_id, ply).unwrap()), "ply {}", ply);
+        }
+        assert!(repo.get_board_at(id, 2 * SNAPSHOT_INTERVAL + 4).is_err());
+
+        let result = GameResult { outcome: Outcome::Draw, reason: EndReason::Unknown };
+        repo.finish_game(id, result).unwrap();
+        drop(repo);
+        assert_eq!(EventLogGameRepository::open(&dir.0).unwrap().get_game_result(id).unwrap(), Some(result));
+    }
+
+    #[test]
+    fn test_imported_games_keep_snapshots() {
+        let dir = TempDir::new("import");
+        let memory = InMemoryGameRepository::new();
+        let memory_id = memory.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        shuffle_knights(&memory, memory_id, 0..2 * SNAPSHOT_INTERVAL + 3);
+        let id = EventLogGameRepository::open(&dir.0).unwrap().import_game(memory.get_game(memory_id).unwrap()).unwrap();
+
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        let snapshots: Vec<usize> = repo.read_log(id, |log| Ok(log.snapshots.iter().map(|(at, _)| *at).collect())).unwrap();
+        assert_eq!(snapshots, [SNAPSHOT_INTERVAL, 2 * SNAPSHOT_INTERVAL, 2 * SNAPSHOT_INTERVAL + 3]);
+        for ply in 0..=2 * SNAPSHOT_INTERVAL + 3 {
+            assert_eq!(fen::to_fen(&repo.get_board_at(id, ply).unwrap()),
+                       fen::to_fen(&memory.get_board_at(memory_id, ply).unwrap()), "ply {}", ply);
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        let dir = TempDir::new("recovery");
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        shuffle_knights(&repo, id, 0..3);
+        drop(repo);
+        let path = log_path(&dir, id);
+        let length = fs::metadata(&path).unwrap().len();
+
+        // a move that was only half written when the server went down
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"event\":\"moved\",\"record\":{\"mv\":").unwrap();
+        // and a game that had not been created yet
+        File::create(Path::new(&dir.0).join("12345.log")).unwrap();
+
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), length);
+        assert!(!Path::new(&dir.0).join("12345.log").exists());
+        assert!(matches!(repo.get_game(12345), Err(GameError::DoesNotExist)));
+        assert_eq!(repo.get_game(id).unwrap().get_moves().len(), 3);
+        // the log carries on from where it was cut back to
+        shuffle_knights(&repo, id, 3..4);
+        drop(repo);
+        assert_eq!(EventLogGameRepository::open(&dir.0).unwrap().get_game(id).unwrap().get_moves().len(), 4);
+    }
+
+    #[test]
+    fn test_corrupt_logs_are_set_aside() {
+        let dir = TempDir::new("corrupt");
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        let mut ids = vec![];
+        for _ in 0..3 {
+            let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+            shuffle_knights(&repo, id, 0..SNAPSHOT_INTERVAL + 2);
+            ids.push(id);
+        }
+        drop(repo);
+
+        // a damaged first line, with whole events after it
+        let log = fs::read_to_string(log_path(&dir, ids[0])).unwrap();
+        fs::write(log_path(&dir, ids[0]), log.replacen("created", "creat", 1)).unwrap();
+        // a snapshot that reads well but does not go with its moves
+        let log = fs::read_to_string(log_path(&dir, ids[1])).unwrap();
+        let ply = format!("\"ply\":{}", SNAPSHOT_INTERVAL);
+        fs::write(log_path(&dir, ids[1]), log.replacen(&ply, "\"ply\":3", 1)).unwrap();
+
+        let repo = EventLogGameRepository::open(&dir.0).unwrap();
+        for id in ids[..2].iter() {
+            assert!(matches!(repo.get_game(*id), Err(GameError::DoesNotExist)));
+            assert!(!log_path(&dir, *id).exists());
+            assert!(log_path(&dir, *id).with_extension("log.corrupt").exists());
+        }
+        assert_eq!(repo.get_game(ids[2]).unwrap().get_moves().len(), SNAPSHOT_INTERVAL + 2);
+    }
+}
@@ -0,0 +1,231 @@
+use super::board::{Board, CastlingRights, Color, Piece, PieceKind, Position};
+use super::engine::ChessEngine;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+const BOARD_SIZE: i32 = 8;
+
+/// Builds a board from Forsyth-Edwards Notation. The move counters may be left off, in which
+/// case they default to `0 1`.
+pub fn parse_fen(fen: &str) -> Result<Board, String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() != 4 && fields.len() != 6 {
+        return Err(format!("Expected 6 fields in FEN but found {}", fields.len()));
+    }
+    let mut board = Board::new(BOARD_SIZE)?;
+    parse_placement(&mut board, fields[0])?;
+    board.set_turn(match fields[1] {
+        "w" => Color::White,
+        "b" => Color::Black,
+        other => return Err(format!("Invalid side to move '{}'", other)),
+    });
+    board.set_castling_rights(parse_castling(fields[2])?);
+    board.set_en_passant(parse_en_passant(&board, fields[3])?);
+    // the side to move could otherwise take the king
+    if ChessEngine::is_in_check(&board, board.get_turn().opposite()) {
+        return Err(String::from("The side not to move is in check"));
+    }
+    if fields.len() == 6 {
+        board.set_halfmove_clock(fields[4].parse::<u32>()
+            .map_err(|_| format!("Invalid halfmove clock '{}'", fields[4]))?);
+        board.set_fullmove_number(match fields[5].parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Invalid fullmove number '{}'", fields[5])),
+        });
+    }
+    Ok(board)
+}
+
+pub fn to_fen(board: &Board) -> String {
+    let turn = match board.get_turn() {
+        Color::White => "w",
+        Color::Black => "b",
+    };
+    let en_passant = match board.get_en_passant() {
+        Some(target) => target.to_algebraic(),
+        None => String::from("-"),
+    };
+    format!("{} {} {} {} {} {}", placement_to_fen(board), turn,
+            castling_to_fen(&board.get_castling_rights()), en_passant,
+            board.get_halfmove_clock(), board.get_fullmove_number())
+}
+
+fn parse_placement(board: &mut Board, placement: &str) -> Result<(), String> {
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != BOARD_SIZE as usize {
+        return Err(format!("Expected {} ranks in FEN but found {}", BOARD_SIZE, ranks.len()));
+    }
+    let mut kings = (0, 0);
+    // ranks are listed from Black's side down to White's
+    for (i, rank) in ranks.iter().enumerate() {
+        let row = BOARD_SIZE - 1 - i as i32;
+        let mut col = 0;
+        for c in rank.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                col += empty as i32;
+                continue;
+            }
+            let kind = PieceKind::from_char(c.to_ascii_uppercase())
+                .ok_or_else(|| format!("Invalid piece '{}' in FEN", c))?;
+            let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+            if col >= BOARD_SIZE {
+                return Err(format!("Rank {} in FEN has too many squares", row + 1));
+            }
+            if kind == PieceKind::King {
+                match color {
+                    Color::White => kings.0 += 1,
+                    Color::Black => kings.1 += 1,
+                }
+            }
+            board.place_piece(&Position::new(row, col), Piece::new(kind, color))?;
+            col += 1;
+        }
+        if col != BOARD_SIZE {
+            return Err(format!("Rank {} in FEN does not have {} squares", row + 1, BOARD_SIZE));
+        }
+    }
+    if kings != (1, 1) {
+        return Err(String::from("Each side must have exactly one king"));
+    }
+    Ok(())
+}
+
+fn parse_castling(castling: &str) -> Result<CastlingRights, String> {
+    let mut rights = CastlingRights::default();
+    if castling == "-" {
+        return Ok(rights);
+    }
+    for c in castling.chars() {
+        match c {
+            'K' => rights.white_king_side = true,
+            'Q' => rights.white_queen_side = true,
+            'k' => rights.black_king_side = true,
+            'q' => rights.black_queen_side = true,
+            _ => return Err(format!("Invalid castling rights '{}'", castling)),
+        }
+    }
+    Ok(rights)
+}
+
+fn parse_en_passant(board: &Board, square: &str) -> Result<Option<Position>, String> {
+    if square == "-" {
+        return Ok(None);
+    }
+    // the target sits behind a pawn the side to move's opponent just pushed two squares, so
+    // the pawn must be in front of it and the squares it passed over must be empty
+    let (expected_row, forward) = if board.get_turn() == Color::White { (5, -1) } else { (2, 1) };
+    let pushed = Piece::new(PieceKind::Pawn, board.get_turn().opposite());
+    match Position::from_algebraic(square) {
+        Some(target) if target.row == expected_row
+            && board.get_space(&Position::new(target.row + forward, target.col)) == Ok(Some(&pushed))
+            && board.get_space(&target) == Ok(None)
+            && board.get_space(&Position::new(target.row - forward, target.col)) == Ok(None) => Ok(Some(target)),
+        _ => Err(format!("Invalid en passant square '{}'", square)),
+    }
+}
+
+fn placement_to_fen(board: &Board) -> String {
+    let mut ranks = vec![];
+    for row in (0..board.get_size()).rev() {
+        let mut rank = String::new();
+        let mut empty = 0;
+        for col in 0..board.get_size() {
+            match board.get_space(&Position::new(row, col)) {
+                Ok(Some(piece)) => {
+                    if empty > 0 {
+                        rank.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    let c = piece.kind.to_char();
+                    rank.push(if piece.color == Color::White { c } else { c.to_ascii_lowercase() });
+                }
+                _ => empty += 1,
+            }
+        }
+        if empty > 0 {
+            rank.push_str(&empty.to_string());
+        }
+        ranks.push(rank);
+    }
+    ranks.join("/")
+}
+
+fn castling_to_fen(rights: &CastlingRights) -> String {
+    let flags = [
+        (rights.white_king_side, 'K'),
+        (rights.white_queen_side, 'Q'),
+        (rights.black_king_side, 'k'),
+        (rights.black_queen_side, 'q'),
+    ];
+    let res: String = flags.iter().filter(|(allowed, _)| *allowed).map(|(_, c)| *c).collect();
+    if res.is_empty() { String::from("-") } else { res }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::{BoardSetup, ChessEngine, Move};
+
+    #[test]
+    fn test_starting_position() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Basic)?;
+        assert_eq!(to_fen(&board), STARTING_FEN);
+
+        let parsed = parse_fen(STARTING_FEN)?;
+        assert_eq!(parsed.get_piece_positions(), board.get_piece_positions());
+        assert_eq!(parsed.get_castling_rights(), CastlingRights::all());
+        assert_eq!(parsed.get_turn(), Color::White);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fen_after_moves() -> Result<(), String> {
+        let mut board = ChessEngine::create_board(BoardSetup::Basic)?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(1, 4), Position::new(3, 4), None))?;
+        assert_eq!(to_fen(&board), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(7, 6), Position::new(5, 5), None))?;
+        ChessEngine::execute_move(&mut board, &Move::new(Position::new(0, 4), Position::new(1, 4), None))?;
+        assert_eq!(to_fen(&board), "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 2 2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), String> {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+        for fen in fens.iter() {
+            assert_eq!(to_fen(&parse_fen(fen)?), *fen);
+        }
+        let board = ChessEngine::create_board(BoardSetup::Fen(fens[2].to_string()))?;
+        assert_eq!(board.get_en_passant(), Some(Position::new(5, 5)));
+        assert!(ChessEngine::legal_moves(&board, &Position::new(4, 4))?.iter().any(|mv| mv.to == Position::new(5, 5)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_fen() {
+        let fens = [
+            "",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQQBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e4 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0",
+            // no pawn was pushed past the en passant square
+            "4k3/8/8/3P4/8/8/8/4K3 w - e6 0 1",
+            "4k3/8/4p3/3P4/8/8/8/4K3 w - e6 0 1",
+            // Black to move could take White's king
+            "4k3/8/8/8/8/8/4r3/4K3 b - - 0 1",
+        ];
+        for fen in fens.iter() {
+            assert!(parse_fen(fen).is_err(), "{}", fen);
+        }
+    }
+}
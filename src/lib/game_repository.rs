@@ -1,75 +1,299 @@
 use super::board::Board;
+use super::engine::{ChessEngine, GameResult, Move, Undo};
 use super::errors::GameError;
 use rand::{Rng};
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct GameRepository {
-    games: HashMap<u32, RwLock<Game>>
+/// Plays a move on a game's board in place, given the board and the number of moves played on
+/// it so far. It makes the move and describes it, along with the result when the move ends the
+/// game, or fails and leaves the board as it was.
+pub type PlayMove<'a> = Box<dyn FnOnce(&mut Board, usize) -> Result<(Undo, MoveRecord, Option<GameResult>), GameError> + 'a>;
+
+/// Where games are kept. The controller only talks to storage through this, so backends can
+/// be swapped without touching it.
+pub trait GameRepository: Send + Sync {
+    fn create_game(&self, board: Board) -> Result<u32, GameError>;
+
+    fn get_game(&self, id: u32) -> Result<Game, GameError>;
+
+    fn get_latest_game_board(&self, id: u32) -> Result<Board, GameError> {
+        Ok(self.get_game(id)?.board)
+    }
+
+    fn get_game_result(&self, id: u32) -> Result<Option<GameResult>, GameError> {
+        Ok(self.get_game(id)?.result)
+    }
+
+    /// The board after the first `ply` moves of the game.
+    fn get_board_at(&self, id: u32, ply: usize) -> Result<Board, GameError> {
+        let mut game = self.get_game(id)?;
+        if ply > game.moves.len() {
+            return Err(GameError::InvalidInput(format!("The game has only {} moves", game.moves.len())));
+        }
+        // the undos may not go back all the way, and boards before them are replayed instead
+        let undone = game.moves.len() - game.history.len();
+        if ply < undone {
+            let mut board = game.initial;
+            for record in game.moves[..ply].iter() {
+                ChessEngine::play_unchecked(&mut board, &record.mv).map_err(GameError::Internal)?;
+            }
+            return Ok(board);
+        }
+        for undo in game.history[ply - undone..].iter().rev() {
+            ChessEngine::unmake_move(&mut game.board, undo).map_err(GameError::Internal)?;
+        }
+        Ok(game.board)
+    }
+
+    /// Plays a move in the game, with no other change to it made in between: `play` sees the
+    /// board as it is when the move is stored, and is not called once the game is finished.
+    fn update_game(&self, id: u32, play: PlayMove) -> Result<(), GameError>;
+
+    fn finish_game(&self, id: u32, result: GameResult) -> Result<(), GameError>;
+
+    fn set_game_tags(&self, id: u32, tags: Vec<(String, String)>) -> Result<(), GameError>;
+
+    /// Stores a game played elsewhere, such as an imported one, under a new ID, which is
+    /// returned. Either all of the game is stored or none of it is.
+    fn import_game(&self, game: Game) -> Result<u32, GameError>;
+}
+
+/// Picks a random game ID that `is_taken` says is free.
+pub fn unique_game_id<F>(is_taken: F) -> Result<u32, GameError> where F: Fn(u32) -> Result<bool, GameError> {
+    let attempts = 10;
+    let mut rng = rand::thread_rng();
+    for _ in 1..attempts {
+        let id = rng.gen::<u32>();
+        if !is_taken(id)? {
+            return Ok(id)
+        }
+    }
+    Err(GameError::Internal(String::from("Could not generate a Game ID")))
 }
 
-impl GameRepository {
+/// Keeps games in memory, where they last until the server stops. Each game has a lock of
+/// its own, so moves in one game do not wait on another.
+#[derive(Default)]
+pub struct InMemoryGameRepository {
+    games: RwLock<HashMap<u32, RwLock<Game>>>
+}
+
+impl InMemoryGameRepository {
 
-    pub fn new() -> GameRepository {
-        GameRepository {
-            games: HashMap::new()
+    pub fn new() -> InMemoryGameRepository {
+        InMemoryGameRepository {
+            games: RwLock::new(HashMap::new())
         }
     }
 
-    pub fn get_latest_game_board(&self, id: u32) -> Result<Board, GameError> {
-        let game_lock = self.get_game_lock(id)?;
+    fn read_game<T, F>(&self, id: u32, read: F) -> Result<T, GameError> where F: FnOnce(&Game) -> T {
+        let games = self.games.read().unwrap();
+        let game_lock = games.get(&id).ok_or(GameError::DoesNotExist)?;
         let game = game_lock.read().unwrap();
-        let board = game.state_history.get(0).unwrap();
-        Ok((*board).clone())
+        Ok(read(&game))
+    }
+
+    fn write_game<T, F>(&self, id: u32, write: F) -> Result<T, GameError>
+        where F: FnOnce(&mut Game) -> Result<T, GameError> {
+        let games = self.games.read().unwrap();
+        let game_lock = games.get(&id).ok_or(GameError::DoesNotExist)?;
+        let mut game = game_lock.write().unwrap();
+        write(&mut game)
     }
+}
+
+impl GameRepository for InMemoryGameRepository {
 
-    pub fn create_game(&mut self, board: Board) -> Result<u32, String> {
-        let id = self.get_unique_id()?;
-        let state_history = vec![board];
-        self.games.insert(id, RwLock::new(Game {id, state_history}));
+    fn create_game(&self, board: Board) -> Result<u32, GameError> {
+        let mut games = self.games.write().unwrap();
+        let id = unique_game_id(|id| Ok(games.contains_key(&id)))?;
+        games.insert(id, RwLock::new(Game::new(id, board)));
         Ok(id)
     }
 
-    pub fn update_game(&self, id: u32, board: &Board) -> Result<(), GameError> {
-        let game_lock = self.get_game_lock(id)?;
-        let mut game = game_lock.write().unwrap();
-        game.append_state(board);
-        Ok(())
+    fn get_game(&self, id: u32) -> Result<Game, GameError> {
+        self.read_game(id, Game::clone)
     }
 
-    fn get_game_lock(&self, id: u32) -> Result<&RwLock<Game>, GameError> {
-        match self.games.get(&id) {
-            Some(game_lock) => Ok(game_lock),
-            None => Err(GameError::DoesNotExist)
-        }
+    fn get_latest_game_board(&self, id: u32) -> Result<Board, GameError> {
+        self.read_game(id, |game| game.board.clone())
     }
 
-    fn get_unique_id(&self) -> Result<u32, String> {
-        let attempts = 10;
-        for _ in 1..attempts {
-            let id = GameRepository::generate_random_id();
-            if let None = self.games.get(&id) {
-                return Ok(id)
-            }
-        }
-        Err(String::from("Could not generate a Game ID"))
+    fn get_game_result(&self, id: u32) -> Result<Option<GameResult>, GameError> {
+        self.read_game(id, |game| game.result)
+    }
+
+    fn update_game(&self, id: u32, play: PlayMove) -> Result<(), GameError> {
+        self.write_game(id, |game| game.play(play))
+    }
+
+    fn finish_game(&self, id: u32, result: GameResult) -> Result<(), GameError> {
+        self.write_game(id, |game| {
+            game.set_result(Some(result));
+            Ok(())
+        })
     }
 
-    fn generate_random_id() -> u32 {
-        let mut rng = rand::thread_rng();
-        rng.gen::<u32>()
+    fn set_game_tags(&self, id: u32, tags: Vec<(String, String)>) -> Result<(), GameError> {
+        self.write_game(id, |game| {
+            game.set_tags(tags);
+            Ok(())
+        })
     }
+
+    fn import_game(&self, mut game: Game) -> Result<u32, GameError> {
+        let mut games = self.games.write().unwrap();
+        let id = unique_game_id(|id| Ok(games.contains_key(&id)))?;
+        game.id = id;
+        games.insert(id, RwLock::new(game));
+        Ok(id)
+    }
+}
+
+/// The current UTC date as YYYY.MM.DD, see http://howardhinnant.github.io/date_algorithms.html
+pub fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// A move as it was played, along with its Standard Algebraic Notation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub mv: Move,
+    pub san: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Game {
     id: u32,
-    state_history: Vec<Board>,
+    date: String,
+    /// PGN tags the game was imported with.
+    tags: Vec<(String, String)>,
+    initial: Board,
+    board: Board,
+    /// What the latest moves changed, oldest first, to take them back with. A game read back
+    /// from storage may only have these for its last few moves.
+    history: Vec<Undo>,
+    moves: Vec<MoveRecord>,
+    result: Option<GameResult>,
 }
 
 impl Game {
-    pub fn append_state(&mut self, board: &Board) {
-        self.state_history.insert(0, (*board).clone())
+    /// A game with no moves played yet from `board`, dated today.
+    pub fn new(id: u32, board: Board) -> Game {
+        Game::with_date(id, today(), board)
+    }
+
+    pub fn with_date(id: u32, date: String, board: Board) -> Game {
+        let initial = board.clone();
+        Game { id, date, tags: vec![], initial, board, history: vec![], moves: vec![], result: None }
+    }
+
+    /// A game read back from storage, where `moves` led from `initial` to `board` and `history`
+    /// holds what the last of them changed.
+    pub fn resume(id: u32, date: String, initial: Board, moves: Vec<MoveRecord>, board: Board,
+                  history: Vec<Undo>) -> Game {
+        Game { id, date, tags: vec![], initial, board, history, moves, result: None }
+    }
+
+    /// Plays a move on the board in place, keeping what `unmake_move` needs to take it back.
+    /// A finished game takes no more moves.
+    pub fn play(&mut self, play: PlayMove) -> Result<(), GameError> {
+        if self.result.is_some() {
+            return Err(GameError::NotAllowed);
+        }
+        let (undo, record, result) = play(&mut self.board, self.moves.len())?;
+        self.history.push(undo);
+        self.moves.push(record);
+        self.result = result;
+        Ok(())
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn get_date(&self) -> &str {
+        &self.date
+    }
+
+    pub fn get_tags(&self) -> &Vec<(String, String)> {
+        &self.tags
+    }
+
+    pub fn set_tags(&mut self, tags: Vec<(String, String)>) {
+        self.tags = tags;
+    }
+
+    pub fn get_initial_board(&self) -> &Board {
+        &self.initial
+    }
+
+    pub fn get_board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn get_history(&self) -> &Vec<Undo> {
+        &self.history
+    }
+
+    pub fn get_moves(&self) -> &Vec<MoveRecord> {
+        &self.moves
+    }
+
+    pub fn get_result(&self) -> Option<GameResult> {
+        self.result
+    }
+
+    pub fn set_result(&mut self, result: Option<GameResult>) {
+        self.result = result;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::board::Position;
+    use super::super::engine::{EndReason, Outcome};
+    use super::super::fen;
+
+    fn play(repo: &dyn GameRepository, id: u32, from: Position, to: Position) -> Result<(), GameError> {
+        repo.update_game(id, Box::new(move |board, _| {
+            let undo = ChessEngine::make_move(board, &Move::new(from, to, None)).map_err(GameError::Internal)?;
+            Ok((undo, MoveRecord { mv: undo.mv, san: String::new() }, None))
+        }))
+    }
+
+    #[test]
+    fn test_in_memory_repository() {
+        let repo = InMemoryGameRepository::new();
+        let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        play(&repo, id, Position::new(1, 4), Position::new(3, 4)).unwrap();
+        assert!(play(&repo, id, Position::new(1, 3), Position::new(3, 3)).is_err());
+
+        let game = repo.get_game(id).unwrap();
+        assert_eq!(game.get_moves().len(), 1);
+        assert_eq!(game.get_history()[0].mv.to, Position::new(3, 4));
+        assert_eq!(fen::to_fen(&repo.get_latest_game_board(id).unwrap()),
+                   "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(fen::to_fen(game.get_initial_board()), fen::STARTING_FEN);
+
+        let result = GameResult { outcome: Outcome::Draw, reason: EndReason::Unknown };
+        repo.finish_game(id, result).unwrap();
+        assert_eq!(repo.get_game_result(id).unwrap(), Some(result));
+        assert!(matches!(repo.get_game(id.wrapping_add(1)), Err(GameError::DoesNotExist)));
     }
 }
@@ -1,5 +1,15 @@
+pub mod bitboard;
 pub mod board;
 pub mod engine;
+pub mod event_log_repository;
+pub mod evaluation;
+pub mod fen;
 pub mod game_repository;
+pub mod pgn;
+pub mod san;
+pub mod sqlite_repository;
+pub mod transposition_table;
+pub mod uci;
+pub mod zobrist;
 pub mod controller;
 pub mod errors;
\ No newline at end of file
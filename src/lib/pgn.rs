@@ -0,0 +1,369 @@
+use super::board::{Board, Color};
+
+/// Tags every PGN game must carry, in the order they must appear.
+pub const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const MAX_LINE_LENGTH: usize = 80;
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// A game read from a PGN file. Only the main line is meant to be played; side lines are
+/// kept on the move they replace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    /// Comments before the first move.
+    pub comments: Vec<String>,
+    pub moves: Vec<PgnMove>,
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PgnMove {
+    pub san: String,
+    /// Numeric annotation glyphs, including those written as `!`, `?` and the like.
+    pub nags: Vec<u32>,
+    pub comments: Vec<String>,
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u32),
+    OpenVariation,
+    CloseVariation,
+    Move(String),
+    Result(String),
+}
+
+/// Reads every game in a PGN file.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, String> {
+    let mut games = vec![];
+    let mut game = PgnGame::default();
+    // the main line sits at the bottom, with any variation being read on top of it
+    let mut lines: Vec<Vec<PgnMove>> = vec![vec![]];
+    let mut has_content = false;
+    for token in tokenize(text)? {
+        match token {
+            Token::Tag(name, value) => {
+                if !lines[0].is_empty() || lines.len() > 1 {
+                    return Err(format!("Tag '{}' found in the middle of a game", name));
+                }
+                game.tags.push((name, value));
+            }
+            Token::Comment(comment) => {
+                let in_main_line = lines.len() == 1;
+                match lines.last_mut().unwrap().last_mut() {
+                    Some(last) => last.comments.push(comment),
+                    None if in_main_line => game.comments.push(comment),
+                    // comments opening a variation are dropped
+                    None => (),
+                }
+            }
+            Token::Nag(nag) => match lines.last_mut().unwrap().last_mut() {
+                Some(last) => last.nags.push(nag),
+                None => return Err(format!("Annotation ${} does not follow a move", nag)),
+            },
+            Token::OpenVariation => {
+                if lines.last().unwrap().is_empty() {
+                    return Err(String::from("Variation does not follow a move"));
+                }
+                lines.push(vec![]);
+            }
+            Token::CloseVariation => {
+                if lines.len() == 1 {
+                    return Err(String::from("Unmatched ')' in PGN"));
+                }
+                let variation = lines.pop().unwrap();
+                lines.last_mut().unwrap().last_mut().unwrap().variations.push(variation);
+            }
+            Token::Move(san) => {
+                let (san, nag) = split_suffix_annotation(&san);
+                let mut pgn_move = PgnMove { san, ..PgnMove::default() };
+                pgn_move.nags.extend(nag);
+                lines.last_mut().unwrap().push(pgn_move);
+            }
+            Token::Result(result) => {
+                if lines.len() > 1 {
+                    return Err(String::from("Game ends inside a variation"));
+                }
+                game.moves = lines.pop().unwrap();
+                game.result = result;
+                games.push(game);
+                game = PgnGame::default();
+                lines = vec![vec![]];
+                has_content = false;
+                continue;
+            }
+        }
+        has_content = true;
+    }
+    if lines.len() > 1 {
+        return Err(String::from("Unterminated variation in PGN"));
+    }
+    // tolerate a missing termination marker on the last game
+    if has_content {
+        game.moves = lines.pop().unwrap();
+        game.result = String::from("*");
+        games.push(game);
+    }
+    Ok(games)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        let at_line_start = line_start;
+        line_start = c == '\n';
+        match c {
+            c if c.is_whitespace() => (),
+            // escape lines are for other programs
+            '%' if at_line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                }
+            }
+            '[' => {
+                let mut inner = String::new();
+                let mut in_string = false;
+                let mut escaped = false;
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("Unterminated tag in PGN")),
+                        Some(']') if !in_string => break,
+                        Some('"') if !escaped => in_string = !in_string,
+                        Some('\\') if in_string && !escaped => {
+                            escaped = true;
+                            continue;
+                        }
+                        Some(c) => inner.push(c),
+                    }
+                    escaped = false;
+                }
+                let mut parts = inner.trim().splitn(2, char::is_whitespace);
+                let name = parts.next().unwrap_or("").to_string();
+                let value = parts.next().unwrap_or("").trim().to_string();
+                if name.is_empty() {
+                    return Err(String::from("Tag without a name in PGN"));
+                }
+                tokens.push(Token::Tag(name, value));
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("Unterminated comment in PGN")),
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                    }
+                }
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            ';' => {
+                let mut comment = String::new();
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line_start = true;
+                        break;
+                    }
+                    comment.push(c);
+                }
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '(' => tokens.push(Token::OpenVariation),
+            ')' => tokens.push(Token::CloseVariation),
+            '$' => {
+                let mut digits = String::new();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                let nag = digits.parse::<u32>().map_err(|_| String::from("Invalid annotation in PGN"))?;
+                tokens.push(Token::Nag(nag));
+            }
+            _ => {
+                let mut symbol = c.to_string();
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "[]{}();$".contains(*next) {
+                        break;
+                    }
+                    symbol.push(*next);
+                    chars.next();
+                }
+                tokens.extend(symbol_tokens(&symbol));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// a symbol is a result, a move, or a move number possibly run together with the move after it
+fn symbol_tokens(symbol: &str) -> Option<Token> {
+    if RESULTS.contains(&symbol) {
+        return Some(Token::Result(symbol.to_string()));
+    }
+    let digits = symbol.find(|c: char| !c.is_ascii_digit()).unwrap_or(symbol.len());
+    let rest = &symbol[digits..];
+    if digits > 0 && (rest.is_empty() || rest.starts_with('.')) {
+        let san = rest.trim_start_matches('.');
+        return if san.is_empty() { None } else { Some(Token::Move(san.to_string())) };
+    }
+    Some(Token::Move(symbol.to_string()))
+}
+
+fn split_suffix_annotation(san: &str) -> (String, Option<u32>) {
+    let stripped = san.trim_end_matches(['!', '?']);
+    let nag = match &san[stripped.len()..] {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    };
+    (stripped.to_string(), nag)
+}
+
+/// Writes a game in PGN export format. `tags` should hold the seven tag roster in order,
+/// `initial` is the position the moves were played from and `result` is the game
+/// termination marker (`1-0`, `0-1`, `1/2-1/2` or `*`).
+pub fn write_pgn(tags: &[(String, String)], initial: &Board, moves: &[String], result: &str) -> String {
+    let mut pgn = String::new();
+    for (name, value) in tags.iter() {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(value)));
+    }
+    pgn.push('\n');
+
+    let mut tokens = vec![];
+    let mut number = initial.get_fullmove_number();
+    let mut turn = initial.get_turn();
+    for (i, san) in moves.iter().enumerate() {
+        if turn == Color::White {
+            tokens.push(format!("{}.", number));
+        } else if i == 0 {
+            tokens.push(format!("{}...", number));
+        }
+        tokens.push(san.clone());
+        if turn == Color::Black {
+            number += 1;
+        }
+        turn = turn.opposite();
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    pgn
+}
+
+fn escape_tag_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::{BoardSetup, ChessEngine};
+
+    fn roster(result: &str) -> Vec<(String, String)> {
+        SEVEN_TAG_ROSTER.iter()
+            .map(|name| (name.to_string(), String::from(if *name == "Result" { result } else { "?" })))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_pgn() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Basic)?;
+        let moves: Vec<String> = ["f3", "e5", "g4", "Qh4#"].iter().map(|m| m.to_string()).collect();
+        let pgn = write_pgn(&roster("0-1"), &board, &moves, "0-1");
+        assert_eq!(pgn, "[Event \"?\"]\n[Site \"?\"]\n[Date \"?\"]\n[Round \"?\"]\n[White \"?\"]\n\
+                         [Black \"?\"]\n[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_pgn() -> Result<(), String> {
+        let text = "[Event \"F/S Return Match\"]\n\
+                    [White \"Fischer, Robert J.\"]\n\
+                    [Annotator \"A \\\"quoted\\\" name\"]\n\
+                    % an escaped line\n\
+                    {Opening comment} 1. e4 e5 2.Nf3 $1 Nc6 {Develops} (2... d6 3. d4 (3. Bc4) exd4) \
+                    3. Bb5!? a6 ; to the end of the line\n\
+                    4... Nf6 1/2-1/2\n\n\
+                    [Event \"Second\"]\n1. d4 *";
+        let games = parse_pgn(text)?;
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(game.get_tag("White"), Some("Fischer, Robert J."));
+        assert_eq!(game.get_tag("Annotator"), Some("A \"quoted\" name"));
+        assert_eq!(game.comments, vec![String::from("Opening comment")]);
+        assert_eq!(game.result, "1/2-1/2");
+        let sans: Vec<&str> = game.moves.iter().map(|m| m.san.as_str()).collect();
+        assert_eq!(sans, vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Nf6"]);
+        assert_eq!(game.moves[2].nags, vec![1]);
+        assert_eq!(game.moves[4].nags, vec![5]);
+        assert_eq!(game.moves[3].comments, vec![String::from("Develops")]);
+        assert_eq!(game.moves[5].comments, vec![String::from("to the end of the line")]);
+        let variation = &game.moves[3].variations[0];
+        assert_eq!(variation.len(), 3);
+        assert_eq!(variation[1].variations[0][0].san, "Bc4");
+
+        assert_eq!(games[1].get_tag("Event"), Some("Second"));
+        assert_eq!(games[1].moves[0].san, "d4");
+        assert_eq!(games[1].result, "*");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_pgn() {
+        assert!(parse_pgn("1. e4 (e5").is_err());
+        assert!(parse_pgn("1. e4 e5)").is_err());
+        assert!(parse_pgn("1. e4 {unterminated").is_err());
+        assert!(parse_pgn("[Event \"?\"").is_err());
+        assert!(parse_pgn("1. e4 [Event \"?\"]").is_err());
+        assert_eq!(parse_pgn("").unwrap(), vec![]);
+        assert_eq!(parse_pgn("1. e4").unwrap()[0].result, "*");
+    }
+
+    #[test]
+    fn test_write_pgn_from_black_and_wrap() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Fen(
+            String::from("4k3/8/8/8/8/8/8/4K3 b - - 0 30")))?;
+        let moves: Vec<String> = (0..40).map(|i| if i % 2 == 0 { "Kd7" } else { "Kd2" }.to_string()).collect();
+        let pgn = write_pgn(&roster("*"), &board, &moves, "*");
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.starts_with("30... Kd7 31. Kd2 Kd7"));
+        assert!(movetext.trim_end().ends_with("50. Kd2 *"));
+        assert!(movetext.lines().count() > 1);
+        assert!(movetext.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        Ok(())
+    }
+}
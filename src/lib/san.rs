@@ -0,0 +1,236 @@
+use super::board::{Board, Color, PieceKind, Position};
+use super::engine::{ChessEngine, GameStatus, Move, MoveKind};
+
+/// Writes a move in Standard Algebraic Notation. `board` is the position before the move,
+/// which must be legal.
+pub fn move_to_san(board: &Board, mv: &Move) -> Result<String, String> {
+    let (from, to) = (&mv.from, &mv.to);
+    let piece = match board.get_space(from)? {
+        Some(piece) => *piece,
+        None => return Err(String::from("The from space does not contain a piece to move")),
+    };
+    let mut after = board.clone();
+    let mv = ChessEngine::make_move(&mut after, mv)?.mv;
+
+    let mut san = String::new();
+    if mv.is_castle() {
+        san.push_str(if mv.kind == MoveKind::KingsideCastle { "O-O" } else { "O-O-O" });
+    } else if piece.kind == PieceKind::Pawn {
+        if mv.is_capture() {
+            san.push(file_char(from));
+            san.push('x');
+        }
+        san.push_str(&to.to_algebraic());
+        if let Some(kind) = mv.promotion {
+            san.push('=');
+            san.push(kind.to_char());
+        }
+    } else {
+        san.push(piece.kind.to_char());
+        san.push_str(&disambiguation(board, from, to)?);
+        if mv.is_capture() {
+            san.push('x');
+        }
+        san.push_str(&to.to_algebraic());
+    }
+    match ChessEngine::game_status(&after) {
+        GameStatus::Checkmate => san.push('#'),
+        GameStatus::Check => san.push('+'),
+        _ => (),
+    }
+    Ok(san)
+}
+
+/// Finds the legal move `san` describes in `board`. Check, mate and annotation suffixes are
+/// ignored, and castling may be written with zeros as well as with the letter O.
+pub fn parse_san(board: &Board, san: &str) -> Result<Move, String> {
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+    let color = board.get_turn();
+    let home_row = if color == Color::White { 0 } else { board.get_size() - 1 };
+    match text {
+        "O-O" | "0-0" => return castling_move(board, home_row, 6, san),
+        "O-O-O" | "0-0-0" => return castling_move(board, home_row, 2, san),
+        _ => (),
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+    let kind = match chars.first().and_then(|c| PieceKind::from_char(*c)) {
+        Some(PieceKind::Pawn) => return Err(format!("Invalid move '{}'", san)),
+        Some(kind) => {
+            chars.remove(0);
+            kind
+        }
+        None => PieceKind::Pawn,
+    };
+    // promotions are written e8=Q, though e8Q turns up as well
+    let mut promotion = None;
+    if kind == PieceKind::Pawn && chars.len() > 2 {
+        if let Some(promoted) = PieceKind::from_char(chars[chars.len() - 1]) {
+            promotion = Some(promoted);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+    if chars.len() < 2 {
+        return Err(format!("Invalid move '{}'", san));
+    }
+    let dest_name: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    let to = Position::from_algebraic(&dest_name).ok_or_else(|| format!("Invalid move '{}'", san))?;
+    let mut file = None;
+    let mut rank = None;
+    for c in chars.iter() {
+        match c {
+            'a'..='h' if file.is_none() => file = Some(*c as i32 - 'a' as i32),
+            '1'..='8' if rank.is_none() => rank = Some(*c as i32 - '1' as i32),
+            'x' | ':' => (),
+            _ => return Err(format!("Invalid move '{}'", san)),
+        }
+    }
+
+    let mut candidates = vec![];
+    for (pos, piece) in board.get_piece_positions().iter() {
+        if piece.kind != kind || piece.color != color
+            || file.is_some_and(|f| f != pos.col) || rank.is_some_and(|r| r != pos.row) {
+            continue;
+        }
+        // a pawn without a file only pushes straight ahead
+        if kind == PieceKind::Pawn && file.is_none() && pos.col != to.col {
+            continue;
+        }
+        if ChessEngine::legal_moves(board, pos)?.iter().any(|mv| mv.to == to) {
+            candidates.push(*pos);
+        }
+    }
+    let from = match candidates.len() {
+        0 => return Err(format!("Illegal move '{}'", san)),
+        1 => candidates[0],
+        _ => return Err(format!("Ambiguous move '{}'", san)),
+    };
+    if ChessEngine::is_promotion(board, &from, &to) != promotion.is_some() {
+        return Err(format!("Invalid promotion in move '{}'", san));
+    }
+    ChessEngine::legal_moves(board, &from)?.into_iter()
+        .find(|mv| mv.to == to && mv.promotion == promotion)
+        .ok_or_else(|| format!("Invalid promotion in move '{}'", san))
+}
+
+fn castling_move(board: &Board, row: i32, col: i32, san: &str) -> Result<Move, String> {
+    let from = Position::new(row, 4);
+    let to = Position::new(row, col);
+    let is_king = matches!(board.get_space(&from), Ok(Some(piece)) if piece.kind == PieceKind::King);
+    let castle = if is_king { ChessEngine::legal_moves(board, &from)?.into_iter().find(|mv| mv.to == to) } else { None };
+    castle.ok_or_else(|| format!("Illegal move '{}'", san))
+}
+
+// the file, rank or whole square of `from` when another piece of the same kind could also
+// move to `to`
+fn disambiguation(board: &Board, from: &Position, to: &Position) -> Result<String, String> {
+    let piece = board.get_space(from)?.unwrap();
+    let mut rivals = vec![];
+    for (pos, other) in board.get_piece_positions().iter() {
+        if pos != from && other.kind == piece.kind && other.color == piece.color
+            && ChessEngine::legal_moves(board, pos)?.iter().any(|mv| mv.to == *to) {
+            rivals.push(*pos);
+        }
+    }
+    if rivals.is_empty() {
+        Ok(String::new())
+    } else if rivals.iter().all(|pos| pos.col != from.col) {
+        Ok(file_char(from).to_string())
+    } else if rivals.iter().all(|pos| pos.row != from.row) {
+        Ok((from.row + 1).to_string())
+    } else {
+        Ok(from.to_algebraic())
+    }
+}
+
+fn file_char(pos: &Position) -> char {
+    std::char::from_u32((pos.col + 97) as u32).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::BoardSetup;
+    use super::super::fen;
+
+    fn san(fen: &str, from: &str, to: &str, promotion: Option<PieceKind>) -> String {
+        let board = fen::parse_fen(fen).unwrap();
+        let mv = Move::new(Position::from_algebraic(from).unwrap(), Position::from_algebraic(to).unwrap(), promotion);
+        move_to_san(&board, &mv).unwrap()
+    }
+
+    #[test]
+    fn test_basic_moves() -> Result<(), String> {
+        let board = ChessEngine::create_board(BoardSetup::Basic)?;
+        assert_eq!(move_to_san(&board, &Move::new(Position::new(1, 4), Position::new(3, 4), None))?, "e4");
+        assert_eq!(move_to_san(&board, &Move::new(Position::new(0, 6), Position::new(2, 5), None))?, "Nf3");
+        assert!(move_to_san(&board, &Move::new(Position::new(0, 6), Position::new(1, 4), None)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_captures_and_checks() {
+        let fen = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/5Q2/PPPP1PPP/RNB1KBNR w KQkq d6 0 3";
+        assert_eq!(san(fen, "e4", "d5", None), "exd5");
+        assert_eq!(san(fen, "f3", "f7", None), "Qxf7+");
+        assert_eq!(san("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5", "f6", None),
+                   "exf6");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "a1", "a8", None), "Ra8#");
+    }
+
+    #[test]
+    fn test_castling_and_promotion() {
+        let fen = "r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1", "g1", None), "O-O");
+        assert_eq!(san(fen, "e1", "c1", None), "O-O-O");
+        assert_eq!(san(fen, "b7", "b8", Some(PieceKind::Queen)), "b8=Q+");
+        assert_eq!(san(fen, "b7", "a8", Some(PieceKind::Knight)), "bxa8=N");
+    }
+
+    fn parse(fen: &str, san: &str) -> Result<(Position, Position, Option<PieceKind>), String> {
+        parse_san(&fen::parse_fen(fen).unwrap(), san).map(|mv| (mv.from, mv.to, mv.promotion))
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let square = |name| Position::from_algebraic(name).unwrap();
+        assert_eq!(parse(fen::STARTING_FEN, "e4"), Ok((square("e2"), square("e4"), None)));
+        assert_eq!(parse(fen::STARTING_FEN, "Nf3!?"), Ok((square("g1"), square("f3"), None)));
+        assert!(parse(fen::STARTING_FEN, "e5").is_err());
+        assert!(parse(fen::STARTING_FEN, "Ke2").is_err());
+        assert!(parse(fen::STARTING_FEN, "Pe4").is_err());
+        assert!(parse(fen::STARTING_FEN, "x").is_err());
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+        assert_eq!(parse(fen, "O-O"), Ok((square("e8"), square("g8"), None)));
+        assert_eq!(parse(fen, "0-0-0"), Ok((square("e8"), square("c8"), None)));
+        let fen = "r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(parse(fen, "bxa8=N"), Ok((square("b7"), square("a8"), Some(PieceKind::Knight))));
+        assert_eq!(parse(fen, "b8Q+"), Ok((square("b7"), square("b8"), Some(PieceKind::Queen))));
+        assert!(parse(fen, "b8").is_err());
+
+        let fen = "4k3/8/8/8/8/1N3N2/4K3/R6R w - - 0 1";
+        assert!(parse(fen, "Nd4").is_err());
+        assert_eq!(parse(fen, "Nfd4"), Ok((square("f3"), square("d4"), None)));
+        assert_eq!(parse(fen, "Rhd1"), Ok((square("h1"), square("d1"), None)));
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(parse(fen, "exf6"), Ok((square("e5"), square("f6"), None)));
+        let mv = parse_san(&fen::parse_fen(fen).unwrap(), "exf6").unwrap();
+        assert_eq!((mv.kind, mv.captured), (MoveKind::EnPassant, Some(PieceKind::Pawn)));
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        let fen = "4k3/8/8/8/8/1N3N2/4K3/R6R w - - 0 1";
+        assert_eq!(san(fen, "b3", "d4", None), "Nbd4");
+        assert_eq!(san(fen, "a1", "d1", None), "Rad1");
+        let fen = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1", "a3", None), "R1a3");
+        let fen = "7k/8/8/8/Q1Q5/8/Q7/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a4", "b3", None), "Qa4b3");
+    }
+}
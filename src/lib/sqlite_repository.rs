@@ -0,0 +1,397 @@
+use super::board::{Board, PieceKind, Position};
+use super::engine::{ChessEngine, GameResult, Move, Undo};
+use super::errors::GameError;
+use super::fen;
+use super::game_repository::{self, Game, GameRepository, MoveRecord, PlayMove};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::sync::Mutex;
+
+// each migration brings the schema up one version, which SQLite keeps as its user_version
+const MIGRATIONS: [&str; 2] = [
+    "CREATE TABLE games (
+        id INTEGER PRIMARY KEY,
+        date TEXT NOT NULL,
+        initial_fen TEXT NOT NULL,
+        tags TEXT NOT NULL DEFAULT '[]',
+        result TEXT
+    );
+    CREATE TABLE moves (
+        game_id INTEGER NOT NULL REFERENCES games (id),
+        ply INTEGER NOT NULL,
+        from_square TEXT NOT NULL,
+        to_square TEXT NOT NULL,
+        promotion TEXT,
+        san TEXT NOT NULL,
+        PRIMARY KEY (game_id, ply)
+    );",
+    // the board after the last move, and what each move changed, as JSON. Games stored before
+    // these have neither until they are next played in.
+    "ALTER TABLE games ADD COLUMN board_fen TEXT;
+    ALTER TABLE moves ADD COLUMN undo TEXT;",
+];
+
+/// Keeps games in a SQLite database, so they outlast the server. A game is stored as its
+/// starting position, its moves along with what each of them changed, and the board they lead
+/// to, so that neither reading a game nor playing in it has to replay its moves.
+pub struct SqliteGameRepository {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteGameRepository {
+
+    /// Opens the database at `path`, creating it if need be, and brings its schema up to date.
+    pub fn open(path: &str) -> Result<SqliteGameRepository, String> {
+        let mut connection = Connection::open(path)
+            .map_err(|err| format!("Could not open the database {}: {}", path, err))?;
+        SqliteGameRepository::migrate(&mut connection)
+            .map_err(|err| format!("Could not migrate the database {}: {}", path, err))?;
+        Ok(SqliteGameRepository { connection: Mutex::new(connection) })
+    }
+
+    fn migrate(connection: &mut Connection) -> Result<(), String> {
+        connection.execute_batch("PRAGMA foreign_keys = ON;").map_err(|err| err.to_string())?;
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|err| err.to_string())?;
+        if version > MIGRATIONS.len() {
+            return Err(format!("The schema is at version {}, newer than this server knows", version));
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction().map_err(|err| err.to_string())?;
+            transaction.execute_batch(migration).map_err(|err| err.to_string())?;
+            transaction.pragma_update(None, "user_version", i + 1).map_err(|err| err.to_string())?;
+            transaction.commit().map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn load_game(transaction: &Transaction, id: u32) -> Result<Game, GameError> {
+        let row = transaction.query_row(
+            "SELECT date, initial_fen, tags, result, board_fen FROM games WHERE id = ?1", params![id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?,
+                      row.get::<_, Option<String>>(3)?, row.get::<_, Option<String>>(4)?)),
+        ).optional().map_err(internal)?;
+        let (date, initial_fen, tags, result, board_fen) = row.ok_or(GameError::DoesNotExist)?;
+        let initial = fen::parse_fen(&initial_fen).map_err(GameError::Internal)?;
+
+        let mut statement = transaction.prepare(
+            "SELECT from_square, to_square, promotion, san, undo FROM moves WHERE game_id = ?1 ORDER BY ply",
+        ).map_err(internal)?;
+        let rows = statement.query_map(params![id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?, row.get::<_, Option<String>>(4)?))
+        }).map_err(internal)?;
+        let mut moves = vec![];
+        let mut undos = vec![];
+        for row in rows {
+            let (from, to, promotion, san, undo) = row.map_err(internal)?;
+            let mv = Move::new(square(&from)?, square(&to)?, match promotion {
+                Some(letter) => Some(piece_kind(&letter)?),
+                None => None,
+            });
+            moves.push(MoveRecord { mv, san });
+            undos.push(undo.map(|undo| from_json::<Undo>(&undo)).transpose()?);
+        }
+
+        let mut game = match (board_fen, undos.into_iter().collect::<Option<Vec<Undo>>>()) {
+            (Some(board_fen), Some(history)) => {
+                // the undos hold the moves as they were played, kind and capture included
+                for (record, undo) in moves.iter_mut().zip(history.iter()) {
+                    record.mv = undo.mv;
+                }
+                let board = fen::parse_fen(&board_fen).map_err(GameError::Internal)?;
+                Game::resume(id, date, initial, moves, board, history)
+            }
+            // stored before the board and the undos were, so the moves are replayed, which
+            // also works out their kinds and captures
+            _ => {
+                let mut game = Game::with_date(id, date, initial);
+                for record in moves {
+                    game.play(Box::new(|board, _| {
+                        let undo = ChessEngine::make_move(board, &record.mv).map_err(GameError::Internal)?;
+                        Ok((undo, MoveRecord { mv: undo.mv, san: record.san }, None))
+                    }))?;
+                }
+                game
+            }
+        };
+        game.set_tags(from_json(&tags)?);
+        // the result comes last, as a finished game takes no more moves
+        if let Some(result) = result {
+            game.set_result(Some(from_json(&result)?));
+        }
+        Ok(game)
+    }
+
+    fn insert_move(transaction: &Transaction, id: u32, ply: usize, record: &MoveRecord, undo: &Undo) -> Result<(), GameError> {
+        let mv = &record.mv;
+        transaction.execute(
+            "INSERT INTO moves (game_id, ply, from_square, to_square, promotion, san, undo)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![id, ply, mv.from.to_algebraic(), mv.to.to_algebraic(),
+                    mv.promotion.map(|kind| kind.to_char().to_string()), record.san, to_json(undo)?],
+        ).map_err(internal)?;
+        Ok(())
+    }
+
+    // runs `sql`, an update of the game's row that takes the new value and then the ID
+    fn update_row(&self, id: u32, sql: &str, value: String) -> Result<(), GameError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(sql, params![value, id]).map_err(internal)? {
+            0 => Err(GameError::DoesNotExist),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GameRepository for SqliteGameRepository {
+
+    fn create_game(&self, board: Board) -> Result<u32, GameError> {
+        let connection = self.connection.lock().unwrap();
+        let id = game_repository::unique_game_id(|id| {
+            connection.query_row("SELECT 1 FROM games WHERE id = ?1", params![id], |_| Ok(()))
+                .optional().map(|row| row.is_some()).map_err(internal)
+        })?;
+        let game = Game::new(id, board);
+        connection.execute(
+            "INSERT INTO games (id, date, initial_fen, board_fen) VALUES (?1, ?2, ?3, ?3)",
+            params![id, game.get_date(), fen::to_fen(game.get_initial_board())],
+        ).map_err(internal)?;
+        Ok(id)
+    }
+
+    fn get_game(&self, id: u32) -> Result<Game, GameError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(internal)?;
+        SqliteGameRepository::load_game(&transaction, id)
+    }
+
+    fn get_latest_game_board(&self, id: u32) -> Result<Board, GameError> {
+        let board_fen = {
+            let connection = self.connection.lock().unwrap();
+            connection.query_row("SELECT board_fen FROM games WHERE id = ?1", params![id],
+                                 |row| row.get::<_, Option<String>>(0))
+                .optional().map_err(internal)?.ok_or(GameError::DoesNotExist)?
+        };
+        match board_fen {
+            Some(board_fen) => fen::parse_fen(&board_fen).map_err(GameError::Internal),
+            None => Ok(self.get_game(id)?.get_board().clone()),
+        }
+    }
+
+    fn get_game_result(&self, id: u32) -> Result<Option<GameResult>, GameError> {
+        let connection = self.connection.lock().unwrap();
+        let result: Option<String> = connection.query_row("SELECT result FROM games WHERE id = ?1", params![id],
+                                                          |row| row.get(0))
+            .optional().map_err(internal)?.ok_or(GameError::DoesNotExist)?;
+        match result {
+            Some(result) => from_json(&result),
+            None => Ok(None),
+        }
+    }
+
+    fn update_game(&self, id: u32, play: PlayMove) -> Result<(), GameError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(internal)?;
+        let row = transaction.query_row(
+            "SELECT board_fen, result IS NOT NULL, (SELECT COUNT(*) FROM moves WHERE game_id = ?1)
+             FROM games WHERE id = ?1", params![id],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, bool>(1)?, row.get::<_, usize>(2)?)),
+        ).optional().map_err(internal)?;
+        let (board_fen, finished, ply) = row.ok_or(GameError::DoesNotExist)?;
+        // a finished game takes no more moves
+        if finished {
+            return Err(GameError::NotAllowed);
+        }
+        let mut board = match board_fen {
+            Some(board_fen) => fen::parse_fen(&board_fen).map_err(GameError::Internal)?,
+            // stored before the board and the undos were: replayed this once, and kept from now on
+            None => {
+                let game = SqliteGameRepository::load_game(&transaction, id)?;
+                for (ply, undo) in game.get_history().iter().enumerate() {
+                    transaction.execute("UPDATE moves SET undo = ?1 WHERE game_id = ?2 AND ply = ?3",
+                                        params![to_json(undo)?, id, ply]).map_err(internal)?;
+                }
+                game.get_board().clone()
+            }
+        };
+        let (undo, record, result) = play(&mut board, ply)?;
+        SqliteGameRepository::insert_move(&transaction, id, ply, &record, &undo)?;
+        transaction.execute("UPDATE games SET board_fen = ?1, result = ?2 WHERE id = ?3",
+                            params![fen::to_fen(&board), result.as_ref().map(to_json).transpose()?, id])
+            .map_err(internal)?;
+        transaction.commit().map_err(internal)
+    }
+
+    fn finish_game(&self, id: u32, result: GameResult) -> Result<(), GameError> {
+        self.update_row(id, "UPDATE games SET result = ?1 WHERE id = ?2", to_json(&result)?)
+    }
+
+    fn set_game_tags(&self, id: u32, tags: Vec<(String, String)>) -> Result<(), GameError> {
+        self.update_row(id, "UPDATE games SET tags = ?1 WHERE id = ?2", to_json(&tags)?)
+    }
+
+    fn import_game(&self, game: Game) -> Result<u32, GameError> {
+        if game.get_history().len() != game.get_moves().len() {
+            return Err(GameError::Internal(String::from("Only games that can take back every move can be stored")));
+        }
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(internal)?;
+        let id = game_repository::unique_game_id(|id| {
+            transaction.query_row("SELECT 1 FROM games WHERE id = ?1", params![id], |_| Ok(()))
+                .optional().map(|row| row.is_some()).map_err(internal)
+        })?;
+        transaction.execute(
+            "INSERT INTO games (id, date, initial_fen, tags, result, board_fen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, game.get_date(), fen::to_fen(game.get_initial_board()), to_json(game.get_tags())?,
+                    game.get_result().as_ref().map(to_json).transpose()?, fen::to_fen(game.get_board())],
+        ).map_err(internal)?;
+        for (ply, (record, undo)) in game.get_moves().iter().zip(game.get_history().iter()).enumerate() {
+            SqliteGameRepository::insert_move(&transaction, id, ply, record, undo)?;
+        }
+        transaction.commit().map_err(internal)?;
+        Ok(id)
+    }
+}
+
+fn internal(err: rusqlite::Error) -> GameError {
+    GameError::Internal(err.to_string())
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, GameError> {
+    serde_json::to_string(value).map_err(|err| GameError::Internal(err.to_string()))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, GameError> {
+    serde_json::from_str(text).map_err(|err| GameError::Internal(format!("Invalid stored JSON: {}", err)))
+}
+
+fn square(name: &str) -> Result<Position, GameError> {
+    Position::from_algebraic(name).ok_or_else(|| GameError::Internal(format!("Invalid stored square '{}'", name)))
+}
+
+fn piece_kind(letter: &str) -> Result<PieceKind, GameError> {
+    letter.chars().next().and_then(PieceKind::from_char)
+        .ok_or_else(|| GameError::Internal(format!("Invalid stored piece '{}'", letter)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::{EndReason, MoveKind, Outcome};
+    use super::super::san;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // a database file of its own for each test, removed when it is done
+    struct TempDatabase(String);
+
+    impl TempDatabase {
+        fn new(name: &str) -> TempDatabase {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+            let path = std::env::temp_dir().join(format!("chess-{}-{}-{}.db", name, std::process::id(), nanos));
+            TempDatabase(path.to_str().unwrap().to_string())
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn play_san(repo: &dyn GameRepository, id: u32, text: &str) -> Result<(), GameError> {
+        repo.update_game(id, Box::new(|board, _| {
+            let mv = san::parse_san(board, text).map_err(GameError::InvalidInput)?;
+            let san = san::move_to_san(board, &mv).map_err(GameError::Internal)?;
+            let undo = ChessEngine::make_move(board, &mv).map_err(GameError::Internal)?;
+            Ok((undo, MoveRecord { mv: undo.mv, san }, None))
+        }))
+    }
+
+    #[test]
+    fn test_games_survive_reopening() {
+        let database = TempDatabase::new("reopen");
+        let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/5N2/PPPP1PPP/RNBQKB1R b KQkq e3 0 3";
+        let id = {
+            let repo = SqliteGameRepository::open(&database.0).unwrap();
+            let id = repo.create_game(fen::parse_fen(fen).unwrap()).unwrap();
+            for text in ["dxe3", "Bc4", "exd2+", "Nbxd2", "Nf6", "O-O"].iter() {
+                play_san(&repo, id, text).unwrap();
+            }
+            assert!(play_san(&repo, id, "Ke2").is_err());
+            repo.set_game_tags(id, vec![(String::from("White"), String::from("Morphy"))]).unwrap();
+            id
+        };
+
+        let repo = SqliteGameRepository::open(&database.0).unwrap();
+        let game = repo.get_game(id).unwrap();
+        assert_eq!(fen::to_fen(game.get_initial_board()), fen);
+        let sans: Vec<&str> = game.get_moves().iter().map(|record| record.san.as_str()).collect();
+        assert_eq!(sans, vec!["dxe3", "Bc4", "exd2+", "Nbxd2", "Nf6", "O-O"]);
+        assert_eq!(game.get_moves()[0].mv.kind, MoveKind::EnPassant);
+        assert_eq!(game.get_moves()[5].mv.kind, MoveKind::KingsideCastle);
+        assert_eq!(game.get_history().len(), 6);
+        assert_eq!(fen::to_fen(&repo.get_latest_game_board(id).unwrap()),
+                   "rnbqkb1r/ppp1pppp/5n2/8/2B5/5N2/PPPN1PPP/R1BQ1RK1 b kq - 2 6");
+        assert_eq!(game.get_tags(), &vec![(String::from("White"), String::from("Morphy"))]);
+        assert_eq!(repo.get_game_result(id).unwrap(), None);
+
+        let result = GameResult { outcome: Outcome::WhiteWins, reason: EndReason::Unknown };
+        repo.finish_game(id, result).unwrap();
+        assert_eq!(repo.get_game_result(id).unwrap(), Some(result));
+        assert_eq!(repo.get_game(id).unwrap().get_result(), Some(result));
+    }
+
+    #[test]
+    fn test_missing_games() {
+        let database = TempDatabase::new("missing");
+        let repo = SqliteGameRepository::open(&database.0).unwrap();
+        let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        let other = id.wrapping_add(1);
+        assert!(matches!(repo.get_game(other), Err(GameError::DoesNotExist)));
+        assert!(matches!(repo.get_game_result(other), Err(GameError::DoesNotExist)));
+        assert!(matches!(play_san(&repo, other, "e4"), Err(GameError::DoesNotExist)));
+        assert!(matches!(repo.set_game_tags(other, vec![]), Err(GameError::DoesNotExist)));
+    }
+
+    #[test]
+    fn test_games_stored_before_boards() {
+        let database = TempDatabase::new("legacy");
+        let repo = SqliteGameRepository::open(&database.0).unwrap();
+        let id = repo.create_game(fen::parse_fen(fen::STARTING_FEN).unwrap()).unwrap();
+        for text in ["e4", "d5", "exd5"].iter() {
+            play_san(&repo, id, text).unwrap();
+        }
+        // as the first version of the schema left them
+        repo.connection.lock().unwrap().execute_batch(&format!(
+            "UPDATE games SET board_fen = NULL WHERE id = {0}; UPDATE moves SET undo = NULL WHERE game_id = {0};", id,
+        )).unwrap();
+
+        let game = repo.get_game(id).unwrap();
+        assert_eq!(game.get_moves()[2].mv.captured, Some(PieceKind::Pawn));
+        assert_eq!(game.get_history().len(), 3);
+        play_san(&repo, id, "Qxd5").unwrap();
+        let missing: usize = repo.connection.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM moves WHERE undo IS NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(missing, 0);
+        assert_eq!(fen::to_fen(&repo.get_latest_game_board(id).unwrap()),
+                   "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3");
+        assert_eq!(fen::to_fen(&repo.get_board_at(id, 2).unwrap()),
+                   "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2");
+    }
+
+    #[test]
+    fn test_migrations() {
+        let database = TempDatabase::new("migrations");
+        SqliteGameRepository::open(&database.0).unwrap();
+        // opening an up to date database again leaves it as it is
+        let repo = SqliteGameRepository::open(&database.0).unwrap();
+        let version: usize = repo.connection.lock().unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        repo.connection.lock().unwrap().pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        drop(repo);
+        assert!(SqliteGameRepository::open(&database.0).is_err());
+    }
+}
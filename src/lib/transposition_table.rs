@@ -0,0 +1,113 @@
+use super::engine::Move;
+use std::mem;
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+/// How a stored score relates to the position's true score, which the search only knows
+/// exactly when it fell inside the alpha-beta window.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    /// The search failed high, so the true score is at least this.
+    Lower,
+    /// The search failed low, so the true score is at most this.
+    Upper,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: i32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+/// A fixed-size table of search results indexed by Zobrist hash. Each key has a single slot,
+/// so a new entry replaces whatever was there unless that was searched deeper for the same
+/// position.
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE_MB)
+    }
+}
+
+impl TranspositionTable {
+
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let capacity = (size_mb * 1024 * 1024 / mem::size_of::<Option<Entry>>()).max(1);
+        TranspositionTable { entries: vec![None; capacity] }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        match &self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => Some(entry),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let index = self.index(entry.key);
+        match &self.entries[index] {
+            Some(existing) if existing.key == entry.key && existing.depth > entry.depth => (),
+            _ => self.entries[index] = Some(entry),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u64, depth: i32) -> Entry {
+        Entry { key, depth, score: depth * 10, bound: Bound::Exact, best_move: None }
+    }
+
+    #[test]
+    fn test_size() {
+        let table = TranspositionTable::new(1);
+        assert_eq!(table.capacity(), 1024 * 1024 / mem::size_of::<Option<Entry>>());
+        assert_eq!(TranspositionTable::new(0).capacity(), 1);
+    }
+
+    #[test]
+    fn test_probe_and_store() {
+        let mut table = TranspositionTable::new(1);
+        let key = 12345;
+        assert_eq!(table.probe(key), None);
+        table.store(entry(key, 3));
+        assert_eq!(table.probe(key), Some(&entry(key, 3)));
+
+        // a shallower search of the same position keeps the deeper result
+        table.store(entry(key, 2));
+        assert_eq!(table.probe(key).unwrap().depth, 3);
+        table.store(entry(key, 4));
+        assert_eq!(table.probe(key).unwrap().depth, 4);
+
+        // a different position in the same slot replaces it
+        let other = key + table.capacity() as u64;
+        table.store(entry(other, 1));
+        assert_eq!(table.probe(key), None);
+        assert_eq!(table.probe(other), Some(&entry(other, 1)));
+
+        table.clear();
+        assert_eq!(table.probe(other), None);
+    }
+}
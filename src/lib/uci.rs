@@ -0,0 +1,436 @@
+use super::board::{Board, Color, PieceKind, Position};
+use super::engine::{self, ChessEngine, Move, SearchLimits};
+use super::evaluation::{Evaluator, TaperedEvaluator, Weights};
+use super::fen;
+use super::transposition_table::{self, TranspositionTable};
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// the number of moves a clock is spread over when the GUI does not say
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+// kept back from the clock for the time it takes the move to reach the GUI
+const MOVE_OVERHEAD_MS: u64 = 50;
+const MAX_HASH_MB: usize = 1024;
+
+/// A move in the long algebraic notation UCI uses, such as `e2e4` or `e7e8q`.
+pub fn move_to_uci(mv: &Move) -> String {
+    let mut text = mv.from.to_algebraic() + &mv.to.to_algebraic();
+    if let Some(kind) = mv.promotion {
+        text.push(kind.to_char().to_ascii_lowercase());
+    }
+    text
+}
+
+/// Finds the legal move `text` names in `board`.
+pub fn parse_uci_move(board: &Board, text: &str) -> Result<Move, String> {
+    let invalid = || format!("Invalid move '{}'", text);
+    if !text.is_ascii() || (text.len() != 4 && text.len() != 5) {
+        return Err(invalid());
+    }
+    let from = Position::from_algebraic(&text[0..2]).ok_or_else(invalid)?;
+    let to = Position::from_algebraic(&text[2..4]).ok_or_else(invalid)?;
+    let promotion = match text[4..].chars().next() {
+        Some(c) => Some(PieceKind::from_char(c.to_ascii_uppercase()).ok_or_else(invalid)?),
+        None => None,
+    };
+    let mv = Move::new(from, to, promotion);
+    ChessEngine::all_legal_moves(board)?.into_iter()
+        .find(|legal| legal.matches(&mv))
+        .ok_or_else(|| format!("Illegal move '{}'", text))
+}
+
+/// The state of a UCI session: the position the GUI last set up and the search running on
+/// it, if any. Replies go to `out` a line at a time, since the search sends its own from
+/// another thread once it finishes.
+pub struct UciEngine {
+    board: Board,
+    out: Sender<String>,
+    evaluator: Arc<dyn Evaluator>,
+    hash_mb: usize,
+    // handed to the search thread while it runs, and back again once it is joined
+    table: Option<TranspositionTable>,
+    search: Option<(JoinHandle<TranspositionTable>, Arc<AtomicBool>)>,
+}
+
+impl UciEngine {
+
+    pub fn new(out: Sender<String>) -> UciEngine {
+        UciEngine {
+            board: fen::parse_fen(fen::STARTING_FEN).unwrap(),
+            out,
+            evaluator: Arc::new(TaperedEvaluator::default()),
+            hash_mb: transposition_table::DEFAULT_SIZE_MB,
+            table: None,
+            search: None,
+        }
+    }
+
+    /// Handles commands from `input` a line at a time until told to quit or the input ends,
+    /// which also stops any search still running.
+    pub fn run<R: BufRead>(mut self, input: R) {
+        for line in input.lines() {
+            match line {
+                Ok(line) if self.handle(&line) => (),
+                _ => break,
+            }
+        }
+    }
+
+    /// Handles one command from the GUI. Returns false once it has been told to quit.
+    pub fn handle(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let result = match tokens.first() {
+            None => Ok(()),
+            Some(&"uci") => {
+                self.send(format!("id name steelmate {}", env!("CARGO_PKG_VERSION")));
+                self.send(format!("id author {}", env!("CARGO_PKG_AUTHORS")));
+                self.send(format!("option name Hash type spin default {} min 1 max {}",
+                                  transposition_table::DEFAULT_SIZE_MB, MAX_HASH_MB));
+                self.send(String::from("option name Weights type string default <empty>"));
+                self.send(String::from("uciok"));
+                Ok(())
+            }
+            Some(&"isready") => {
+                self.send(String::from("readyok"));
+                Ok(())
+            }
+            Some(&"ucinewgame") => {
+                self.stop();
+                self.table = None;
+                Ok(())
+            }
+            Some(&"position") => {
+                self.stop();
+                self.set_position(&tokens[1..])
+            }
+            Some(&"go") => {
+                self.stop();
+                self.go(&tokens[1..])
+            }
+            Some(&"stop") => {
+                self.stop();
+                Ok(())
+            }
+            Some(&"setoption") => {
+                self.stop();
+                self.set_option(&tokens[1..])
+            }
+            Some(&"quit") => {
+                self.stop();
+                return false;
+            }
+            Some(command) => Err(format!("Unknown command '{}'", command)),
+        };
+        // UCI has no way to report errors, so they only go out as information
+        if let Err(err) = result {
+            self.send(format!("info string {}", err));
+        }
+        true
+    }
+
+    fn send(&self, line: String) {
+        // the other end only goes away once the session is over
+        let _ = self.out.send(line);
+    }
+
+    // position [startpos | fen <fen>] [moves <move>...]
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|arg| *arg == "moves").unwrap_or(args.len());
+        let mut board = match args.first() {
+            Some(&"startpos") => fen::parse_fen(fen::STARTING_FEN)?,
+            Some(&"fen") => fen::parse_fen(&args[1..moves_at].join(" "))?,
+            _ => return Err(String::from("Expected startpos or fen")),
+        };
+        for text in args.iter().skip(moves_at + 1) {
+            let mv = parse_uci_move(&board, text)?;
+            ChessEngine::execute_move(&mut board, &mv)?;
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    // setoption name <name> [value <value>]
+    fn set_option(&mut self, args: &[&str]) -> Result<(), String> {
+        let value_at = args.iter().position(|arg| *arg == "value").unwrap_or(args.len());
+        if args.first() != Some(&"name") {
+            return Err(String::from("Expected an option name"));
+        }
+        let name = args[1..value_at].join(" ");
+        let value = args.get(value_at + 1..).map(|value| value.join(" ")).unwrap_or_default();
+        match name.to_lowercase().as_str() {
+            "hash" => {
+                let size_mb = value.parse::<usize>().ok().filter(|mb| (1..=MAX_HASH_MB).contains(mb))
+                    .ok_or_else(|| format!("Invalid Hash size '{}'", value))?;
+                self.hash_mb = size_mb;
+                self.table = None;
+            }
+            "weights" => {
+                let weights = if value.is_empty() || value == "<empty>" {
+                    Weights::default()
+                } else {
+                    Weights::from_file(&value)?
+                };
+                self.evaluator = Arc::new(TaperedEvaluator::new(weights));
+            }
+            _ => return Err(format!("Unknown option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    // go [depth <plies>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>]
+    //    [movestogo <moves>] [infinite]
+    fn go(&mut self, args: &[&str]) -> Result<(), String> {
+        let mut infinite = false;
+        let mut depth = None;
+        let mut move_time = None;
+        let mut clock = [None; 2];
+        let mut increment = [0; 2];
+        let mut moves_to_go = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut number = || args.next().and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| format!("Expected a number after '{}'", arg));
+            match *arg {
+                "depth" => depth = Some(number()?),
+                "movetime" => move_time = Some(number()?),
+                "wtime" => clock[0] = Some(number()?),
+                "btime" => clock[1] = Some(number()?),
+                "winc" => increment[0] = number()?,
+                "binc" => increment[1] = number()?,
+                "movestogo" => moves_to_go = Some(number()?),
+                // searching until told to stop is what happens without any other limit, but the
+                // best move must also wait for the stop
+                "infinite" => infinite = true,
+                // the rest, such as nodes or searchmoves, are left out along with their values,
+                // which is better than never answering at all
+                _ => (),
+            }
+        }
+        let side = if self.board.get_turn() == Color::White { 0 } else { 1 };
+        let time = move_time.map(Duration::from_millis)
+            .or_else(|| clock[side].map(|remaining| time_budget(remaining, increment[side], moves_to_go)));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let limits = SearchLimits {
+            depth: depth.map_or(engine::MAX_DEPTH, |depth| depth.clamp(1, engine::MAX_DEPTH as u64) as u32),
+            // a time too long to count to is no limit at all
+            deadline: time.and_then(|time| Instant::now().checked_add(time)),
+            stop: Some(stop.clone()),
+            evaluator: self.evaluator.clone(),
+            ..SearchLimits::default()
+        };
+        let board = self.board.clone();
+        let mut table = self.table.take().unwrap_or_else(|| TranspositionTable::new(self.hash_mb));
+        let out = self.out.clone();
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut lines = match ChessEngine::best_moves_with_table(&board, &limits, &mut table) {
+                Ok(result) => search_report(&result),
+                Err(err) => vec![format!("info string {}", err), String::from("bestmove 0000")],
+            };
+            let best_move = lines.pop();
+            for line in lines {
+                let _ = out.send(line);
+            }
+            // an infinite search that runs out of things to search still waits to be stopped
+            while infinite && !stopped.load(Ordering::Relaxed) {
+                thread::park();
+            }
+            if let Some(line) = best_move {
+                let _ = out.send(line);
+            }
+            table
+        });
+        self.search = Some((handle, stop));
+        Ok(())
+    }
+
+    // cuts short the running search, which still reports its best move, and waits for it
+    fn stop(&mut self) {
+        if let Some((handle, stop)) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+            handle.thread().unpark();
+            self.table = handle.join().ok();
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    // an infinite search would otherwise outlive the session it belongs to
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// the info line for the deepest iteration that finished, then the move to play
+fn search_report(result: &engine::SearchResult) -> Vec<String> {
+    let best = match result.moves.first() {
+        Some(best) => best,
+        None => return vec![String::from("bestmove 0000")],
+    };
+    let score = match ChessEngine::mate_in(best.score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", best.score),
+    };
+    let pv: Vec<String> = best.pv.iter().map(move_to_uci).collect();
+    let stats = &result.stats;
+    vec![
+        format!("info depth {} score {} nodes {} nps {} time {} pv {}",
+                stats.depth, score, stats.nodes, stats.nps, stats.time_ms, pv.join(" ")),
+        format!("bestmove {}", move_to_uci(&best.mv)),
+    ]
+}
+
+// how long to think with `remaining` on the clock
+fn time_budget(remaining: u64, increment: u64, moves_to_go: Option<u64>) -> Duration {
+    let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    let budget = (remaining / moves).saturating_add(increment / 4 * 3);
+    let available = remaining.saturating_sub(MOVE_OVERHEAD_MS).max(1);
+    Duration::from_millis(budget.min(available))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+
+    fn session() -> (UciEngine, Receiver<String>) {
+        let (out, replies) = mpsc::channel();
+        (UciEngine::new(out), replies)
+    }
+
+    // the replies up to and including the first that starts with `last`
+    fn replies_until(replies: &Receiver<String>, last: &str) -> Vec<String> {
+        let mut lines = vec![];
+        loop {
+            let line = replies.recv_timeout(Duration::from_secs(60)).unwrap();
+            let done = line.starts_with(last);
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    #[test]
+    fn test_uci_moves() {
+        let board = fen::parse_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let mv = parse_uci_move(&board, "b7a8n").unwrap();
+        assert!(mv.matches(&Move::new(Position::new(6, 1), Position::new(7, 0), Some(PieceKind::Knight))));
+        assert_eq!(mv.captured, Some(PieceKind::Rook));
+        assert_eq!(move_to_uci(&mv), "b7a8n");
+        assert_eq!(move_to_uci(&parse_uci_move(&board, "e1g1").unwrap()), "e1g1");
+        assert!(parse_uci_move(&board, "b7b8").is_err());
+        assert!(parse_uci_move(&board, "e1e3").is_err());
+        assert!(parse_uci_move(&board, "e1").is_err());
+        assert!(parse_uci_move(&board, "b7b8x").is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut uci, replies) = session();
+        assert!(uci.handle("uci"));
+        let lines = replies_until(&replies, "uciok");
+        assert!(lines[0].starts_with("id name steelmate"));
+        assert!(lines.iter().any(|line| line.starts_with("option name Hash")));
+        assert!(uci.handle("isready"));
+        assert_eq!(replies.recv().unwrap(), "readyok");
+        assert!(uci.handle("foo"));
+        assert_eq!(replies.recv().unwrap(), "info string Unknown command 'foo'");
+        assert!(!uci.handle("quit"));
+    }
+
+    #[test]
+    fn test_position() {
+        let (mut uci, replies) = session();
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(fen::to_fen(&uci.board), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        uci.handle("position fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 moves e2e4");
+        assert_eq!(fen::to_fen(&uci.board), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+        // a bad position leaves the last good one in place
+        uci.handle("position startpos moves e2e5");
+        assert_eq!(replies.recv().unwrap(), "info string Illegal move 'e2e5'");
+        assert_eq!(fen::to_fen(&uci.board), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+    }
+
+    #[test]
+    fn test_go() {
+        let (mut uci, replies) = session();
+        uci.handle("position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        uci.handle("go depth 2");
+        let lines = replies_until(&replies, "bestmove");
+        assert!(lines[0].starts_with("info depth 2 score mate 1"), "{}", lines[0]);
+        assert!(lines[0].ends_with("pv a1a8"));
+        assert_eq!(lines[1], "bestmove a1a8");
+
+        uci.handle("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        uci.handle("go wtime 1000 btime 1000");
+        assert_eq!(replies_until(&replies, "bestmove"), vec!["bestmove 0000"]);
+        uci.handle("go depth x");
+        assert_eq!(replies.recv().unwrap(), "info string Expected a number after 'depth'");
+
+        // what is not supported is left out, and there is still a best move
+        uci.handle("position startpos");
+        uci.handle("go ponder nodes 1000 searchmoves e2e4 d2d4 depth 1 mate 3");
+        assert!(replies_until(&replies, "bestmove").last().unwrap().starts_with("bestmove "));
+        uci.handle(&format!("go depth {} movetime {}", u64::MAX, u64::MAX));
+        thread::sleep(Duration::from_millis(50));
+        uci.handle("stop");
+        assert!(replies_until(&replies, "bestmove").last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_stop() {
+        let (mut uci, replies) = session();
+        uci.handle("position startpos");
+        uci.handle("go infinite");
+        thread::sleep(Duration::from_millis(100));
+        uci.handle("stop");
+        let lines = replies_until(&replies, "bestmove");
+        assert!(lines.last().unwrap().len() > "bestmove ".len());
+        uci.handle("isready");
+        assert_eq!(replies.recv().unwrap(), "readyok");
+
+        // a mate is found at once, but the best move still waits for the stop
+        uci.handle("position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        uci.handle("go infinite");
+        let early: Vec<String> = std::iter::from_fn(|| replies.recv_timeout(Duration::from_millis(300)).ok()).collect();
+        assert!(early.iter().all(|line| !line.starts_with("bestmove")), "{:?}", early);
+        uci.handle("stop");
+        assert_eq!(replies_until(&replies, "bestmove").last().unwrap(), "bestmove a1a8");
+    }
+
+    #[test]
+    fn test_input_ending_during_search() {
+        let (uci, replies) = session();
+        uci.run("position startpos\ngo infinite\n".as_bytes());
+        let lines: Vec<String> = replies.iter().collect();
+        assert!(lines.last().unwrap().starts_with("bestmove "), "{:?}", lines);
+    }
+
+    #[test]
+    fn test_set_option() {
+        let (mut uci, replies) = session();
+        uci.handle("setoption name Hash value 1");
+        assert_eq!(uci.hash_mb, 1);
+        uci.handle("setoption name Hash value 0");
+        assert_eq!(replies.recv().unwrap(), "info string Invalid Hash size '0'");
+        uci.handle("setoption name Weights value /no/such/weights.json");
+        assert!(replies.recv().unwrap().starts_with("info string Could not read evaluation weights"));
+        uci.handle("setoption name Ponder value true");
+        assert_eq!(replies.recv().unwrap(), "info string Unknown option 'Ponder'");
+    }
+
+    #[test]
+    fn test_time_budget() {
+        assert_eq!(time_budget(60_000, 0, None), Duration::from_millis(2_000));
+        assert_eq!(time_budget(60_000, 1_000, Some(10)), Duration::from_millis(6_750));
+        assert_eq!(time_budget(100, 1_000, Some(1)), Duration::from_millis(50));
+        assert_eq!(time_budget(u64::MAX, u64::MAX, Some(1)), Duration::from_millis(u64::MAX - MOVE_OVERHEAD_MS));
+    }
+}
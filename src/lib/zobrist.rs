@@ -0,0 +1,133 @@
+use super::board::{Board, CastlingRights, Color, Piece, PieceKind, Position};
+
+// one key per piece kind and color on each of the 64 squares, then the side to move, the
+// four castling rights and the eight en passant files
+const PIECE_KEYS: usize = 0;
+const SIDE_KEY: usize = 2 * 6 * 64;
+const CASTLING_KEYS: usize = SIDE_KEY + 1;
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 4;
+const KEY_COUNT: usize = EN_PASSANT_KEYS + 8;
+
+// generated at compile time from a fixed seed, so hashes are the same from one run to the next
+const KEYS: [u64; KEY_COUNT] = generate_keys(0x5EED_C4E5_5B0A_2D01);
+
+const fn generate_keys(seed: u64) -> [u64; KEY_COUNT] {
+    let mut keys = [0; KEY_COUNT];
+    let mut state = seed;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+pub fn piece_key(piece: &Piece, pos: &Position) -> u64 {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+    let kind = match piece.kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5,
+    };
+    let square = (pos.row * 8 + pos.col) as usize;
+    KEYS[PIECE_KEYS + (color * 6 + kind) * 64 + square]
+}
+
+/// Mixed in while Black is to move.
+pub fn side_key() -> u64 {
+    KEYS[SIDE_KEY]
+}
+
+pub fn castling_key(rights: &CastlingRights) -> u64 {
+    let flags = [rights.white_king_side, rights.white_queen_side, rights.black_king_side, rights.black_queen_side];
+    flags.iter().enumerate()
+        .filter(|(_, allowed)| **allowed)
+        .fold(0, |key, (i, _)| key ^ KEYS[CASTLING_KEYS + i])
+}
+
+/// Only the file of the en passant square matters, as its rank follows from the side to move.
+pub fn en_passant_key(target: Option<Position>) -> u64 {
+    match target {
+        Some(pos) => KEYS[EN_PASSANT_KEYS + pos.col as usize],
+        None => 0,
+    }
+}
+
+/// Hashes `board` from scratch. `Board` keeps its hash up to date as it changes, so this is
+/// only needed to check that it has.
+pub fn hash_board(board: &Board) -> u64 {
+    let mut hash = board.get_piece_positions().iter()
+        .fold(0, |hash, (pos, piece)| hash ^ piece_key(piece, pos));
+    if board.get_turn() == Color::Black {
+        hash ^= side_key();
+    }
+    hash ^ castling_key(&board.get_castling_rights()) ^ en_passant_key(board.get_en_passant())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::engine::{BoardSetup, ChessEngine, Move};
+    use super::super::fen;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let keys: HashSet<u64> = KEYS.iter().copied().collect();
+        assert_eq!(keys.len(), KEY_COUNT);
+        assert!(!keys.contains(&0));
+    }
+
+    #[test]
+    fn test_incremental_hash() -> Result<(), String> {
+        let mut board = ChessEngine::create_board(BoardSetup::Basic)?;
+        assert_eq!(board.get_hash(), hash_board(&board));
+        let start = board.get_hash();
+
+        // knights out and back again reach the same position
+        let moves = [((0, 6), (2, 5)), ((7, 6), (5, 5)), ((2, 5), (0, 6)), ((5, 5), (7, 6))];
+        for (from, to) in moves.iter() {
+            ChessEngine::execute_move(&mut board, &Move::new(Position::new(from.0, from.1), Position::new(to.0, to.1), None))?;
+            assert_eq!(board.get_hash(), hash_board(&board));
+        }
+        assert_eq!(board.get_hash(), start);
+
+        // castling, en passant and promotion all touch more than two squares
+        let fens = [
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", (0, 4), (0, 6), None),
+            ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", (4, 4), (5, 5), None),
+            ("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1", (6, 1), (7, 0), Some(PieceKind::Queen)),
+        ];
+        for (fen, from, to, promotion) in fens.iter() {
+            let mut board = fen::parse_fen(fen)?;
+            assert_eq!(board.get_hash(), hash_board(&board));
+            ChessEngine::execute_move(&mut board, &Move::new(Position::new(from.0, from.1), Position::new(to.0, to.1), *promotion))?;
+            assert_eq!(board.get_hash(), hash_board(&board));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_covers_state() -> Result<(), String> {
+        let hash = |fen: &str| fen::parse_fen(fen).map(|board| board.get_hash());
+        let base = hash("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")?;
+        assert_ne!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1")?);
+        assert_ne!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1")?);
+        assert_ne!(hash("4k3/8/8/8/4Pp2/8/8/4K3 b - e3 0 1")?, hash("4k3/8/8/8/4Pp2/8/8/4K3 b - - 0 1")?);
+        // the clocks are not part of the position
+        assert_eq!(base, hash("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 12 30")?);
+        Ok(())
+    }
+}
@@ -1,14 +1,20 @@
-mod lib;
-
-use actix_web::{web::Data, web::Path, App, HttpResponse, HttpServer, Responder, get, post};
+use actix_web::{error::BlockingError, http::header, web, web::Bytes, web::Data, web::Path, web::Query, App, HttpRequest, HttpResponse, HttpServer, Responder, get, post};
 use actix_http::Response;
-use lib::controller::GameController;
-use lib::errors::GameError;
-use lib::game_repository::GameRepository;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Instant;
+use chess::controller::GameController;
+use chess::engine::ChessEngine;
+use chess::fen;
+use chess::uci;
+use chess::errors::GameError;
+use chess::evaluation::{TaperedEvaluator, Weights};
+use chess::game_repository::{GameRepository, InMemoryGameRepository};
+use chess::event_log_repository::EventLogGameRepository;
+use chess::sqlite_repository::SqliteGameRepository;
 
 // LEFT TO DO:
 // - User Management
-// - Integrate with DB
 // - Infra: Docker, Hosting, SSL
 // - UI
 // - AI
@@ -21,37 +27,109 @@ use lib::game_repository::GameRepository;
 // https://medium.com/better-programming/create-the-match-match-memory-game-in-react-and-vue-js-1026f1df000e
 // https://medium.com/better-programming/vue-js-basics-inputs-events-and-components-1a874528e66a
 
-
 struct AppState {
     game_controller: GameController
 }
 
+// a move can name the ply it was chosen at, here or as the ETag of the game in If-Match, so
+// that it is refused if the game has moved on since
+#[derive(Deserialize)]
+struct MoveQuery {
+    promote: Option<String>,
+    ply: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SanMoveQuery {
+    ply: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct BestMovesQuery {
+    depth: Option<u32>,
+    time_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct NewGameRequest {
+    fen: Option<String>,
+}
+
 #[post("/game")]
-async fn start_game(data: Data<AppState>) -> impl Responder {
-    match data.game_controller.start_game() {
-        Ok(id) => HttpResponse::Ok().body(id.to_string()),
-        Err(msg) =>  HttpResponse::InternalServerError().body(msg),
+async fn start_game(body: Bytes, data: Data<AppState>) -> impl Responder {
+    // the body is optional; without one the game starts from the standard position
+    let request = if body.is_empty() {
+        NewGameRequest { fen: None }
+    } else {
+        match serde_json::from_slice::<NewGameRequest>(&body) {
+            Ok(request) => request,
+            Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+        }
+    };
+    match data.game_controller.start_game(request.fen) {
+        Ok(id) => HttpResponse::Ok().body(id),
+        Err(error) => process_game_error(error),
+    }
+}
+
+#[post("/game/pgn")]
+async fn import_pgn(body: String, data: Data<AppState>) -> impl Responder {
+    match data.game_controller.import_pgn(&body) {
+        Ok(ids) => HttpResponse::Ok().content_type("application/json").body(ids),
+        Err(error) => process_game_error(error),
     }
 }
 
 #[get("/game/{id}")]
 async fn get_game(Path(id): Path<u32>, data: Data<AppState>) -> impl Responder {
-    println!("{}", String::from("Come on man!"));
     match data.game_controller.get_game(id) {
-        Ok(board_json) => HttpResponse::Ok().content_type("application/json").body(board_json),
+        Ok((board_json, etag)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .header(header::ETAG, etag)
+            .body(board_json),
         Err(error) => process_game_error(error),
     }
 }
 
 #[post("/game/{id}/position/{pos}/move/{dest}")]
 async fn post_game_move(Path((id, pos, dest)): Path<(u32, String, String)>,
+                        Query(query): Query<MoveQuery>,
+                        request: HttpRequest,
                         data: Data<AppState>) -> impl Responder {
-    match data.game_controller.play_move(id, pos, dest) {
+    let expected_plies = match expected_plies(&request, query.ply) {
+        Ok(plies) => plies,
+        Err(error) => return process_game_error(error),
+    };
+    match data.game_controller.play_move(id, pos, dest, query.promote, expected_plies.as_deref()) {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(error) => process_game_error(error),
     }
 }
 
+#[post("/game/{id}/move/{san}")]
+async fn post_game_san_move(Path((id, san)): Path<(u32, String)>,
+                            Query(query): Query<SanMoveQuery>,
+                            request: HttpRequest,
+                            data: Data<AppState>) -> impl Responder {
+    let expected_plies = match expected_plies(&request, query.ply) {
+        Ok(plies) => plies,
+        Err(error) => return process_game_error(error),
+    };
+    match data.game_controller.play_san_move(id, &san, expected_plies.as_deref()) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => process_game_error(error),
+    }
+}
+
+#[get("/game/{id}/pgn")]
+async fn get_game_pgn(Path(id): Path<u32>, data: Data<AppState>) -> impl Responder {
+    match data.game_controller.get_game_pgn(id) {
+        Ok(pgn) => HttpResponse::Ok().content_type("application/x-chess-pgn").body(pgn),
+        Err(error) => process_game_error(error),
+    }
+}
+
+// the moves come with the API's square names and, as `uci`, algebraic ones
 #[get("/game/{id}/position/{pos}/options")]
 async fn get_piece_options(Path((id, pos)): Path<(u32, String)>,
                            data: Data<AppState>) -> impl Responder {
@@ -62,33 +140,117 @@ async fn get_piece_options(Path((id, pos)): Path<(u32, String)>,
     }
 }
 
+// the moves come with square names as for the options, along with their SAN
 #[get("/game/{id}/color/{c}/best_moves")]
-async fn get_best_move() -> impl Responder {
-    "Best move"
+async fn get_best_move(Path((id, color)): Path<(u32, String)>,
+                       Query(query): Query<BestMovesQuery>,
+                       data: Data<AppState>) -> impl Responder {
+    // searching takes a while, so it runs on the blocking thread pool rather than holding up
+    // the worker
+    let search = web::block(move || data.game_controller.get_best_moves(id, &color, query.depth, query.time_ms));
+    match search.await {
+        Ok(moves) => HttpResponse::Ok().content_type("application/json").body(moves),
+        Err(BlockingError::Error(error)) => process_game_error(error),
+        Err(BlockingError::Canceled) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// the plies a move may be played at, as the query and If-Match both allow
+fn expected_plies(request: &HttpRequest, ply: Option<usize>) -> Result<Option<Vec<usize>>, GameError> {
+    let if_match = match request.headers().get(header::IF_MATCH) {
+        Some(value) => {
+            let value = value.to_str().map_err(|err| GameError::InvalidInput(err.to_string()))?;
+            GameController::plies_from_if_match(value)?
+        }
+        None => None,
+    };
+    Ok(match (ply, if_match) {
+        (Some(ply), Some(plies)) => Some(plies.into_iter().filter(|matched| *matched == ply).collect()),
+        (Some(ply), None) => Some(vec![ply]),
+        (None, plies) => plies,
+    })
 }
 
 fn process_game_error(error: GameError) -> Response {
     match error {
         GameError::DoesNotExist => HttpResponse::NotFound().finish(),
+        GameError::Conflict => HttpResponse::Conflict().finish(),
         GameError::Internal(msg) => HttpResponse::InternalServerError().body(msg),
         GameError::NotAllowed => HttpResponse::BadRequest().finish(),
+        GameError::InvalidInput(msg) => HttpResponse::BadRequest().body(msg),
+    }
+}
+
+// perft <depth> [FEN]: counts the positions `depth` plies on from the FEN, or from the
+// starting position, and how many of them follow each first move
+fn run_perft(args: &[String]) -> Result<(), String> {
+    let depth = args.first().and_then(|depth| depth.parse::<u32>().ok())
+        .ok_or_else(|| String::from("Usage: perft <depth> [FEN]"))?;
+    let fen = if args.len() > 1 { args[1..].join(" ") } else { String::from(fen::STARTING_FEN) };
+    let board = fen::parse_fen(&fen)?;
+    let start = Instant::now();
+    let counts = ChessEngine::divide(&board, depth)?;
+    for (mv, count) in counts.iter() {
+        println!("{}: {}", uci::move_to_uci(mv), count);
     }
+    let nodes: u64 = counts.iter().map(|(_, count)| count).sum();
+    println!("\nNodes: {}\nTime: {} ms", nodes, start.elapsed().as_millis());
+    Ok(())
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("perft") {
+        return run_perft(&args[2..])
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err));
+    }
+
+    // the evaluation weights can be tuned with --weights <path to a JSON file>
+    let weights = match args.iter().position(|arg| arg == "--weights") {
+        Some(index) => match args.get(index + 1) {
+            Some(path) => Weights::from_file(path)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?,
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "--weights needs a path")),
+        },
+        None => Weights::default(),
+    };
+
+    // games are kept in memory unless --db <path> names a SQLite database to keep them in, or
+    // --event-log <dir> a directory of game logs
+    let option = |name: &str| match args.iter().position(|arg| arg == name) {
+        Some(index) => match args.get(index + 1) {
+            Some(path) => Ok(Some(path.clone())),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} needs a path", name))),
+        },
+        None => Ok(None),
+    };
+    let game_repository: Box<dyn GameRepository> = match (option("--db")?, option("--event-log")?) {
+        (Some(_), Some(_)) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "--db and --event-log cannot be used together"));
+        }
+        (Some(path), None) => Box::new(SqliteGameRepository::open(&path)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?),
+        (None, Some(dir)) => Box::new(EventLogGameRepository::open(&dir)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?),
+        (None, None) => Box::new(InMemoryGameRepository::new()),
+    };
+
     // initialize game bank
     let app_state = Data::new(AppState {
-        game_controller: GameController::new(GameRepository::new())
+        game_controller: GameController::with_evaluator(game_repository, Arc::new(TaperedEvaluator::new(weights)))
     });
 
     HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
             .service(start_game)
+            .service(import_pgn)
             .service(get_game)
             .service(post_game_move)
+            .service(post_game_san_move)
+            .service(get_game_pgn)
             .service(get_piece_options)
             .service(get_best_move)
     })